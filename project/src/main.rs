@@ -1,47 +1,294 @@
-use crate::pack_unpack::packing::create_archive;
-use crate::pack_unpack::unpacking::extract_files;
+use crate::pack_unpack::comparing::{compare_archives, compare_with_filesystem};
+use crate::pack_unpack::converting::{convert_archive, ArchiveFormat};
+use crate::pack_unpack::deb::{build_deb, extract_deb, list_deb};
+use crate::pack_unpack::deleting::delete_members;
+use crate::pack_unpack::digests::verify_archive;
+use crate::pack_unpack::encryption::{read_passphrase, PASSPHRASE_VARIABLE};
+use crate::pack_unpack::images::apply_image;
+use crate::pack_unpack::indexing::{index_archive, index_path, list_members};
+use crate::pack_unpack::layers::diff_layer;
+use crate::pack_unpack::ownership::{load_rules, parse_group, parse_owner, ModeChange};
+use crate::pack_unpack::packing::{
+    create_archive, read_file_list, Dereference, DigestMode, PackOptions, PackRoot,
+};
+use crate::pack_unpack::signing::{generate_key, sign_archive, verify_signature};
+use crate::pack_unpack::unpacking::{extract_files, test_archive, UnpackOptions};
+use crate::pack_unpack::users::IdResolver;
+use crate::pack_unpack::volumes::parse_volume_size;
 use std::io;
 mod pack_unpack;
-fn execute_command(command: Vec<&str>) {
+fn execute_command(command: Vec<&str>) -> bool {
     if command.len() < 2 {
         println!("Invalid command. Use <.tar --help> to find out more.");
-        return;
+        return false;
     }
 
     match command[1] {
         "--help" => {
             println!(
-                "This tool packs or unpacks files in the format .tar or .tar.gz.\n\
+                "This tool packs or unpacks files in the format .tar, .tar.gz, .zip or .cpio.\n\
                 To pack a directory, use the following format:\n\
                 1. For .tar: .tar pack <path_to_directory> [<name_of_archive>]\n\
                 2. For .tar.gz: .tar pack <path_to_directory> -c [<name_of_archive>]\n\
                 If you don't specify the name, a generic archive.tar or archive.tar.gz will be created.\n\
                 Don't include extensions in the name.\n\
-                To unpack, use the following format:\n\
-                .tar unpack <path_to_archive>\n\
-                To close the tool use quit."
+                More paths can follow -C <directory>: each one is read from that directory and stored\n\
+                under its name relative to it (e.g. -C build bin lib), and -C may be given again.\n\
+                -T <list_file> adds the paths listed in a file (or stdin with -T -), one per line or\n\
+                NUL-separated as written by find -print0, relative to the last -C directory. The\n\
+                archive name must come before the first -C, and the directory may be left out:\n\
+                .tar pack -T <list_file> [<name_of_archive>] [-C <directory> <path>...]\n\
+                Symlinks are stored as links; --dereference (-h) stores what they point to instead\n\
+                (links back to a parent directory are skipped), and -H does so only for the paths\n\
+                given on the command line or in -T lists. --one-file-system stores mount points but\n\
+                not the file systems mounted on them.\n\
+                Files with several links are stored once and the other names as hard links to it;\n\
+                --hard-dereference stores each name with its own copy of the data instead.\n\
+                Add --format zip to create <name>.zip instead, or --format cpio (SVR4 newc, as used for\n\
+                initramfs images) or --format odc to create <name>.cpio; unpack and list read both as well.\n\
+                Add --digest=pax to store a SHA-256 digest for every file in pax records,\n\
+                or --digest=manifest to append a MANIFEST member listing them.\n\
+                Add --encrypt to encrypt the archive with a passphrase (read from {} or prompted);\n\
+                encrypted archives get a .enc suffix and are detected automatically by every command.\n\
+                Add --listed-incremental=<snapshot_file> to only archive what changed since the run that\n\
+                wrote the snapshot; restore the full backup and then each increment with\n\
+                .tar unpack <path_to_archive> --incremental (files deleted in between are removed).\n\
+                Add --volume-size=<size> (e.g. 4G, 700M) to split an uncompressed archive into\n\
+                <name>.tar.001, <name>.tar.002, ...; unpack the .001 volume and the rest are found\n\
+                automatically, or list them with --volumes <second>,<third>,...\n\
+                To check the stored digests of an archive (and its signature if a public key is given):\n\
+                .tar verify <path_to_archive> [<public_key>]\n\
+                To sign archives with Ed25519, create a key pair once and sign into <path_to_archive>.sig:\n\
+                .tar keygen <private_key>\n\
+                .tar sign <path_to_archive> <private_key>\n\
+                To refuse extraction of archives without a valid signature:\n\
+                .tar unpack <path_to_archive> --require-signature <public_key>\n\
+                Add --index to also write <name>.tar.idx, which lets single members be extracted\n\
+                without reading the whole archive; .tar index <path_to_archive> builds it afterwards.\n\
+                Add --owner=<name>[:<uid>] and --group=<name>[:<gid>] (or a plain id) to store another\n\
+                owner, --numeric-owner to leave out user and group names, and --mode=<changes> to apply\n\
+                chmod-style changes (e.g. u+rwX,go-w or 0644) to every member. --owner-rules=<file> takes\n\
+                lines of <member_pattern> [owner=..] [group=..] [mode=..], matched like unpack members\n\
+                (e.g. dir/bin/* mode=0755 owner=root:0); later lines win.\n\
+                Both pack and unpack take --transform=<s/regex/replacement/flags> (any delimiter, e.g.\n\
+                --transform='s,^build/,myapp-1.0/,') to rename members; \\1..\\9 and & refer to the match.\n\
+                The regex is a POSIX basic one as in sed, so groups are written \\(..\\).\n\
+                Flags: g replaces every match, i ignores case, x makes the regex extended, a number N\n\
+                replaces the Nth match, and r, s, h limit the rule to regular names, symlink targets\n\
+                or hard-link targets (all three by default) while R, S, H leave them out. Rules given\n\
+                several times or separated by ; are applied in order, each to the result of the previous\n\
+                one; unpack still selects members by their archived names.\n\
+                Add --mtree <spec_file> to also write a BSD mtree spec of what was archived (type, mode,\n\
+                owner, time, size and sha256, paths relative to the packed directory). With\n\
+                --from-mtree <spec_file> the members and their metadata come from the spec instead and\n\
+                only the file contents are read from <path_to_directory>, e.g. to store root:root\n\
+                ownership without running as root.\n\
+                With -c, --seekable compresses in independent gzip frames aligned to members and\n\
+                indexes them, so listing and single-member extraction only decompress what they need.\n\
+                To unpack, use the following format (optionally naming the members to extract):\n\
+                .tar unpack <path_to_archive> [<member>...]\n\
+                Add -C <directory> to unpack somewhere else than the current directory, and --layer to\n\
+                apply a container image layer onto an existing rootfs there: .wh.<name> whiteouts delete\n\
+                <name>, .wh..wh..opq empties its directory, and existing files are replaced.\n\
+                Hard links are made inside that directory (or copied where linking fails); when only some\n\
+                members are unpacked and a link's target is not among them, the first selected link\n\
+                gets the data and the others link to it.\n\
+                When run as root, unpack restores owners by user and group name where the name exists\n\
+                on this system and by the stored ids otherwise; --numeric-owner always uses the ids.\n\
+                To create such a layer from the changes between two trees (a diffID is printed, the\n\
+                SHA-256 of the uncompressed tar; -c writes <name>.tar.gz, default name is layer):\n\
+                .tar diff-layer <old_directory> <new_directory> [<name>] [-c]\n\
+                To unpack all layers of an image in order into a rootfs directory, from an OCI image\n\
+                layout directory (index.json, blobs/) or a docker save tarball; blob digests and diffIDs\n\
+                are verified and gzip or zstd layers are handled:\n\
+                .tar apply-image <image_directory_or_tarball> <rootfs_directory>\n\
+                To list the members of an archive:\n\
+                .tar list <path_to_archive>\n\
+                To check an archive without extracting it:\n\
+                .tar unpack <path_to_archive> --test\n\
+                To remove members (names, directories or * ? patterns) from an archive:\n\
+                .tar delete <path_to_archive> <member>...\n\
+                To compare an archive against the filesystem (default directory is the current one):\n\
+                .tar diff <path_to_archive> [<directory>]\n\
+                To compare two archives (any compression), optionally as JSON:\n\
+                .tar compare <old_archive> <new_archive> [--json]\n\
+                To convert between tar, tar.gz, zip, cpio (newc) and ar, chosen by the output extension;\n\
+                anything the output format cannot store is reported as a warning:\n\
+                .tar convert <input_archive> <output_archive>\n\
+                (an output ending in .a writes a flat ar archive of the regular files).\n\
+                To inspect, unpack (data at the top, control files under DEBIAN) or build Debian packages;\n\
+                build stores everything owned by root:root, the control dir must contain a control file:\n\
+                .tar deb list <package.deb>\n\
+                .tar deb extract <package.deb> <directory>\n\
+                .tar deb build <control_dir> <data_dir> <package.deb>\n\
+                Commands can also be passed as arguments, e.g. project diff <path_to_archive>.\n\
+                To close the tool use quit.",
+                PASSPHRASE_VARIABLE
             );
         }
         "pack" => {
             if command.len() < 3 {
                 println!("Invalid command. Specify the path to the directory to pack. Use <.tar --help> to find out more.");
-                return;
+                return false;
             }
 
-            let path_to_directory = command[2];
-            let compress = command.get(3) == Some(&"-c");
-            let archive_name = if compress {
-                command.get(4).unwrap_or(&"archive").to_string()
+            // Without a directory the paths come from -C and -T alone.
+            let (path_to_directory, first_argument) = if command[2].starts_with('-') {
+                ("", 2)
             } else {
-                command.get(3).unwrap_or(&"archive").to_string()
+                (command[2], 3)
             };
+            let mut options = PackOptions::default();
+            let mut archive_name = "archive".to_string();
+            let mut resolver = IdResolver::default();
+            let mut base_directory: Option<String> = None;
+            let mut arguments = command[first_argument..].iter();
+            while let Some(argument) = arguments.next() {
+                match *argument {
+                    "-c" => options.compress = true,
+                    "--digest=pax" => options.digests = DigestMode::Pax,
+                    "--digest=manifest" => options.digests = DigestMode::Manifest,
+                    "--encrypt" => match read_passphrase() {
+                        Ok(passphrase) => options.passphrase = Some(passphrase),
+                        Err(e) => {
+                            println!("Error reading passphrase: {}", e);
+                            return false;
+                        }
+                    },
+                    argument if argument.starts_with("--listed-incremental=") => {
+                        options.snapshot_file =
+                            Some(argument["--listed-incremental=".len()..].to_string())
+                    }
+                    argument if argument.starts_with("--volume-size=") => {
+                        match parse_volume_size(&argument["--volume-size=".len()..]) {
+                            Ok(volume_size) => options.volume_size = Some(volume_size),
+                            Err(e) => {
+                                println!("Invalid command. {}", e);
+                                return false;
+                            }
+                        }
+                    }
+                    "--index" => options.index = true,
+                    "--dereference" | "-h" => options.dereference = Dereference::Always,
+                    "-H" => options.dereference = Dereference::CommandLine,
+                    "--one-file-system" => options.one_file_system = true,
+                    "--hard-dereference" => options.hard_dereference = true,
+                    argument if argument.starts_with("--transform=") => {
+                        if let Err(e) = options.transforms.add(&argument["--transform=".len()..]) {
+                            println!("Invalid command. {}", e);
+                            return false;
+                        }
+                    }
+                    "--numeric-owner" => options.ownership.numeric_owner = true,
+                    argument if argument.starts_with("--owner=") => {
+                        match parse_owner(&argument["--owner=".len()..], &mut resolver) {
+                            Ok(owner) => options.ownership.owner = Some(owner),
+                            Err(e) => {
+                                println!("Invalid command. {}", e);
+                                return false;
+                            }
+                        }
+                    }
+                    argument if argument.starts_with("--group=") => {
+                        match parse_group(&argument["--group=".len()..], &mut resolver) {
+                            Ok(group) => options.ownership.group = Some(group),
+                            Err(e) => {
+                                println!("Invalid command. {}", e);
+                                return false;
+                            }
+                        }
+                    }
+                    argument if argument.starts_with("--mode=") => {
+                        match ModeChange::parse(&argument["--mode=".len()..]) {
+                            Ok(mode) => options.ownership.mode = Some(mode),
+                            Err(e) => {
+                                println!("Invalid command. {}", e);
+                                return false;
+                            }
+                        }
+                    }
+                    argument if argument.starts_with("--owner-rules=") => {
+                        match load_rules(&argument["--owner-rules=".len()..]) {
+                            Ok(rules) => options.ownership.rules = rules,
+                            Err(e) => {
+                                println!("Invalid command. {}", e);
+                                return false;
+                            }
+                        }
+                    }
+                    "--format" => match arguments.next().copied() {
+                        Some("tar") => options.format = ArchiveFormat::Tar,
+                        Some("zip") => options.format = ArchiveFormat::Zip,
+                        Some("cpio") => options.format = ArchiveFormat::Cpio,
+                        Some("odc") => options.format = ArchiveFormat::CpioOdc,
+                        _ => {
+                            println!("Invalid command. --format needs tar, zip, cpio or odc.");
+                            return false;
+                        }
+                    },
+                    "--seekable" => {
+                        options.seekable = true;
+                        options.index = true;
+                    }
+                    "--mtree" | "--from-mtree" => match arguments.next() {
+                        Some(spec_file) if *argument == "--mtree" => {
+                            options.mtree_file = Some(spec_file.to_string())
+                        }
+                        Some(spec_file) => options.from_mtree = Some(spec_file.to_string()),
+                        None => {
+                            println!("Invalid command. {} needs an mtree spec file.", argument);
+                            return false;
+                        }
+                    },
+                    "-C" => match arguments.next() {
+                        Some(directory) => base_directory = Some(directory.to_string()),
+                        None => {
+                            println!("Invalid command. -C needs a directory.");
+                            return false;
+                        }
+                    },
+                    "-T" => {
+                        let names = match arguments.next().map(|list| read_file_list(list)) {
+                            Some(Ok(names)) => names,
+                            Some(Err(e)) => {
+                                println!("Error reading file list: {}", e);
+                                return false;
+                            }
+                            None => {
+                                println!("Invalid command. -T needs a file list or -.");
+                                return false;
+                            }
+                        };
+                        for name in names {
+                            match PackRoot::resolve(base_directory.as_deref().unwrap_or("."), &name)
+                            {
+                                Ok(roots) => options.roots.extend(roots),
+                                Err(e) => {
+                                    println!("Invalid command. {}", e);
+                                    return false;
+                                }
+                            }
+                        }
+                    }
+                    name if base_directory.is_some() => {
+                        match PackRoot::resolve(base_directory.as_deref().unwrap(), name) {
+                            Ok(roots) => options.roots.extend(roots),
+                            Err(e) => {
+                                println!("Invalid command. {}", e);
+                                return false;
+                            }
+                        }
+                    }
+                    name => archive_name = name.to_string(),
+                }
+            }
 
-            match create_archive(path_to_directory, &archive_name, compress) {
-                Ok(_) => {
-                    let extension = if compress { ".tar.gz" } else { ".tar" };
-                    println!("Successfully created {}{}", archive_name, extension);
+            match create_archive(path_to_directory, &archive_name, &options) {
+                Ok(archive_file_name) => println!("Successfully created {}", archive_file_name),
+                Err(e) => {
+                    println!("Error packing archive: {}", e);
+                    return false;
                 }
-                Err(e) => println!("Error packing archive: {}", e),
             }
         }
         "unpack" => {
@@ -49,27 +296,319 @@ fn execute_command(command: Vec<&str>) {
                 println!(
                     "Invalid command. Specify the archive. Use <.tar --help> to find out more."
                 );
-                return;
+                return false;
             }
 
             let archive_path = command[2];
-            let is_compressed = archive_path.as_bytes()[archive_path.len() - 1] == b'z'
-                && archive_path.as_bytes()[archive_path.len() - 2] == b'g';
-            if !archive_path.ends_with(".tar") || !archive_path.ends_with(".tar.gz") {
+            let mut options = UnpackOptions::default();
+            let mut arguments = command[3..].iter();
+            while let Some(argument) = arguments.next() {
+                match *argument {
+                    "--test" => {}
+                    "--incremental" => options.incremental = true,
+                    "--layer" => options.layer = true,
+                    "--numeric-owner" => options.numeric_owner = true,
+                    argument if argument.starts_with("--transform=") => {
+                        if let Err(e) = options.transforms.add(&argument["--transform=".len()..]) {
+                            println!("Invalid command. {}", e);
+                            return false;
+                        }
+                    }
+                    "-C" => match arguments.next() {
+                        Some(directory) => options.destination = Some(directory.to_string()),
+                        None => {
+                            println!("Invalid command. -C needs a directory.");
+                            return false;
+                        }
+                    },
+                    "--require-signature" => match arguments.next() {
+                        Some(public_key_file) => {
+                            options.signature_key = Some(public_key_file.to_string())
+                        }
+                        None => {
+                            println!(
+                                "Invalid command. --require-signature needs a public key file."
+                            );
+                            return false;
+                        }
+                    },
+                    "--volumes" => match arguments.next() {
+                        Some(volumes) => {
+                            options.volumes = volumes.split(',').map(str::to_string).collect()
+                        }
+                        None => {
+                            println!("Invalid command. --volumes needs a comma-separated list of volumes.");
+                            return false;
+                        }
+                    },
+                    member => options.members.push(member.to_string()),
+                }
+            }
+            let plain_path = archive_path
+                .trim_end_matches(".001")
+                .trim_end_matches(".enc");
+            if !plain_path.ends_with(".tar")
+                && !plain_path.ends_with(".tar.gz")
+                && !plain_path.ends_with(".zip")
+                && !plain_path.ends_with(".cpio")
+            {
                 println!("Unsupported file type!");
+                return false;
+            } else if command.contains(&"--test") {
+                match test_archive(archive_path, &options) {
+                    Ok(members) => println!("{}: {} member(s), no errors", archive_path, members),
+                    Err(e) => {
+                        println!("Archive {} is damaged: {}", archive_path, e);
+                        return false;
+                    }
+                }
             } else {
-                match extract_files(archive_path, is_compressed) {
+                match extract_files(archive_path, &options) {
                     Ok(_) => println!("Successfully unpacked {}", archive_path),
-                    Err(e) => println!("Error unpacking archive: {}", e),
+                    Err(e) => {
+                        println!("Error unpacking archive: {}", e);
+                        return false;
+                    }
+                }
+            }
+        }
+        "delete" => {
+            if command.len() < 4 {
+                println!("Invalid command. Specify the archive and the members to delete. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            let archive_path = command[2];
+            match delete_members(archive_path, &command[3..]) {
+                Ok(removed) => println!("Removed {} member(s) from {}", removed, archive_path),
+                Err(e) => {
+                    println!("Error deleting from archive: {}", e);
+                    return false;
+                }
+            }
+        }
+        "diff" => {
+            if command.len() < 3 {
+                println!("Invalid command. Specify the archive to compare. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            let archive_path = command[2];
+            let directory = command.get(3).unwrap_or(&".");
+            match compare_with_filesystem(archive_path, directory) {
+                Ok(0) => println!("{} matches {}", archive_path, directory),
+                Ok(differing) => {
+                    println!("{} member(s) differ from {}", differing, directory);
+                    return false;
+                }
+                Err(e) => {
+                    println!("Error comparing archive: {}", e);
+                    return false;
+                }
+            }
+        }
+        "compare" => {
+            if command.len() < 4 {
+                println!("Invalid command. Specify the two archives to compare. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            let as_json = command.get(4) == Some(&"--json");
+            match compare_archives(command[2], command[3], as_json) {
+                Ok(0) => {
+                    if !as_json {
+                        println!("{} and {} are identical", command[2], command[3]);
+                    }
+                }
+                Ok(_) => return false,
+                Err(e) => {
+                    println!("Error comparing archives: {}", e);
+                    return false;
+                }
+            }
+        }
+        "convert" => {
+            if command.len() < 4 {
+                println!("Invalid command. Specify the input and output archives. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            match convert_archive(command[2], command[3]) {
+                Ok(members) => println!(
+                    "Converted {} member(s) from {} to {}",
+                    members, command[2], command[3]
+                ),
+                Err(e) => {
+                    println!("Error converting archive: {}", e);
+                    return false;
+                }
+            }
+        }
+        "diff-layer" => {
+            if command.len() < 4 {
+                println!("Invalid command. Specify the old and the new directory. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            let compress = command[4..].contains(&"-c");
+            let layer_name = command[4..]
+                .iter()
+                .find(|argument| **argument != "-c")
+                .copied()
+                .unwrap_or("layer");
+            match diff_layer(command[2], command[3], layer_name, compress) {
+                Ok((layer_file_name, diff_id)) => {
+                    println!("Successfully created {}", layer_file_name);
+                    println!("diffID {}", diff_id);
+                }
+                Err(e) => {
+                    println!("Error creating layer: {}", e);
+                    return false;
+                }
+            }
+        }
+        "apply-image" => {
+            if command.len() < 4 {
+                println!("Invalid command. Specify the image and the rootfs directory. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            match apply_image(command[2], command[3]) {
+                Ok(layers) => println!(
+                    "Applied {} layer(s) from {} onto {}",
+                    layers, command[2], command[3]
+                ),
+                Err(e) => {
+                    println!("Error applying image: {}", e);
+                    return false;
+                }
+            }
+        }
+        "deb" => {
+            let result = match (command.get(2).copied(), command.len()) {
+                (Some("list"), 4) => list_deb(command[3]).map(|members| {
+                    println!(
+                        "{}: {} member(s) in the inner archives",
+                        command[3], members
+                    )
+                }),
+                (Some("extract"), 5) => extract_deb(command[3], command[4])
+                    .map(|()| println!("Successfully unpacked {} into {}", command[3], command[4])),
+                (Some("build"), 6) => build_deb(command[3], command[4], command[5])
+                    .map(|()| println!("Successfully created {}", command[5])),
+                _ => {
+                    println!("Invalid command. Use deb list|extract|build. Use <.tar --help> to find out more.");
+                    return false;
+                }
+            };
+            if let Err(e) = result {
+                println!("Error handling Debian package: {}", e);
+                return false;
+            }
+        }
+        "verify" => {
+            if command.len() < 3 {
+                println!("Invalid command. Specify the archive to verify. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            if let Some(public_key_file) = command.get(3) {
+                match verify_signature(command[2], public_key_file) {
+                    Ok(()) => println!("Signature of {} is valid", command[2]),
+                    Err(e) => {
+                        println!("Signature check failed: {}", e);
+                        return false;
+                    }
+                }
+            }
+            match verify_archive(command[2]) {
+                Ok(0) => println!("All digests in {} match", command[2]),
+                Ok(mismatches) => {
+                    println!("{} digest mismatch(es) in {}", mismatches, command[2]);
+                    return false;
+                }
+                Err(e) => {
+                    println!("Error verifying archive: {}", e);
+                    return false;
+                }
+            }
+        }
+        "list" => {
+            if command.len() < 3 {
+                println!("Invalid command. Specify the archive to list. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            if let Err(e) = list_members(command[2]) {
+                println!("Error listing archive: {}", e);
+                return false;
+            }
+        }
+        "index" => {
+            if command.len() < 3 {
+                println!("Invalid command. Specify the archive to index. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            match index_archive(command[2]) {
+                Ok(members) => println!(
+                    "Indexed {} member(s) of {} into {}",
+                    members,
+                    command[2],
+                    index_path(command[2])
+                ),
+                Err(e) => {
+                    println!("Error indexing archive: {}", e);
+                    return false;
+                }
+            }
+        }
+        "keygen" => {
+            if command.len() < 3 {
+                println!("Invalid command. Specify the private key file to create. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            match generate_key(command[2]) {
+                Ok(public_key_file) => println!(
+                    "Created private key {} and public key {}",
+                    command[2], public_key_file
+                ),
+                Err(e) => {
+                    println!("Error generating key: {}", e);
+                    return false;
+                }
+            }
+        }
+        "sign" => {
+            if command.len() < 4 {
+                println!("Invalid command. Specify the archive and the private key file. Use <.tar --help> to find out more.");
+                return false;
+            }
+
+            match sign_archive(command[2], command[3]) {
+                Ok(signature_file) => println!("Created signature {}", signature_file),
+                Err(e) => {
+                    println!("Error signing archive: {}", e);
+                    return false;
                 }
             }
         }
         _ => {
             println!("Unknown command. Use <.tar --help> to find out more.");
+            return false;
         }
     }
+    true
 }
 fn main() -> io::Result<()> {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    if !arguments.is_empty() {
+        let mut command = vec![".tar"];
+        command.extend(arguments.iter().map(String::as_str));
+        std::process::exit(if execute_command(command) { 0 } else { 1 });
+    }
+
     println!("Hello to my .tar tool.Use <.tar --help> to find out more.");
     loop {
         let mut input = String::new();
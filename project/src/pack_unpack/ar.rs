@@ -0,0 +1,264 @@
+use crate::pack_unpack::entry::{Entry, EntryKind};
+use std::io::{self, Read};
+
+pub const AR_MAGIC: &[u8; 8] = b"!<arch>\n";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ArVariant {
+    #[default]
+    Gnu,
+    Bsd,
+}
+#[derive(Clone, Debug)]
+pub struct ArMember {
+    pub name: String,
+    pub mtime: i64,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub data: Vec<u8>,
+}
+impl ArMember {
+    pub fn new(name: &str, data: Vec<u8>, mtime: i64) -> Self {
+        ArMember {
+            name: name.to_string(),
+            mtime,
+            uid: 0,
+            gid: 0,
+            mode: 0o100644,
+            data,
+        }
+    }
+}
+pub fn is_ar(start: &[u8]) -> bool {
+    start.starts_with(AR_MAGIC)
+}
+fn invalid_ar(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+/// Parses a numeric header field; blank fields, as in the GNU long name table, read as 0.
+fn number_field(field: &[u8], radix: u32, what: &str, member: &str) -> Result<u64, io::Error> {
+    let text = String::from_utf8_lossy(field);
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, radix).map_err(|_| {
+        invalid_ar(format!(
+            "bad {} field '{}' in ar member '{}'",
+            what, text, member
+        ))
+    })
+}
+fn long_name(table: &[u8], offset: usize) -> Result<String, io::Error> {
+    let rest = table
+        .get(offset..)
+        .ok_or_else(|| invalid_ar(format!("long name offset {} is out of range", offset)))?;
+    let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+    Ok(String::from_utf8_lossy(&rest[..end])
+        .trim_end_matches('/')
+        .to_string())
+}
+pub fn read_ar(reader: &mut dyn Read) -> Result<Vec<ArMember>, io::Error> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if !is_ar(&magic) {
+        return Err(invalid_ar("not an ar archive".to_string()));
+    }
+
+    let mut members = Vec::new();
+    let mut long_names = Vec::new();
+    loop {
+        let mut header = [0u8; 60];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        if &header[58..60] != b"`\n" {
+            return Err(invalid_ar("bad ar member header".to_string()));
+        }
+        let raw_name = String::from_utf8_lossy(&header[0..16])
+            .trim_end()
+            .to_string();
+        let size = number_field(&header[48..58], 10, "size", &raw_name)?;
+        let mtime = number_field(&header[16..28], 10, "mtime", &raw_name)?;
+        let uid = number_field(&header[28..34], 10, "uid", &raw_name)?;
+        let gid = number_field(&header[34..40], 10, "gid", &raw_name)?;
+        let mode = number_field(&header[40..48], 8, "mode", &raw_name)?;
+
+        // The size is untrusted, so the data is read as it arrives instead of allocated up front.
+        let mut data = Vec::new();
+        reader.take(size).read_to_end(&mut data)?;
+        if (data.len() as u64) < size {
+            return Err(invalid_ar(format!(
+                "ar archive is truncated inside '{}'",
+                raw_name
+            )));
+        }
+        if size % 2 == 1 {
+            reader.read_exact(&mut [0u8; 1])?;
+        }
+
+        let name = if raw_name == "/" || raw_name == "/SYM64/" || raw_name.starts_with("__.SYMDEF")
+        {
+            continue;
+        } else if raw_name == "//" {
+            long_names = data;
+            continue;
+        } else if let Some(length) = raw_name.strip_prefix("#1/") {
+            // BSD stores long names in front of the member data.
+            let length: usize = length
+                .parse()
+                .map_err(|_| invalid_ar(format!("bad BSD long name '{}'", raw_name)))?;
+            if length > data.len() {
+                return Err(invalid_ar(format!("bad BSD long name '{}'", raw_name)));
+            }
+            let name = String::from_utf8_lossy(&data[..length])
+                .trim_end_matches('\0')
+                .to_string();
+            data.drain(..length);
+            name
+        } else if let Some(offset) = raw_name
+            .strip_prefix('/')
+            .and_then(|offset| offset.parse::<usize>().ok())
+        {
+            long_name(&long_names, offset)?
+        } else {
+            raw_name.trim_end_matches('/').to_string()
+        };
+
+        members.push(ArMember {
+            name,
+            mtime: mtime as i64,
+            uid: uid as u32,
+            gid: gid as u32,
+            mode: mode as u32,
+            data,
+        });
+    }
+    Ok(members)
+}
+fn write_header(
+    ar_buffer: &mut Vec<u8>,
+    name: &str,
+    member: &ArMember,
+    size: usize,
+) -> Result<(), io::Error> {
+    let fields = [
+        ("mtime", member.mtime.max(0).to_string(), 12),
+        ("uid", member.uid.to_string(), 6),
+        ("gid", member.gid.to_string(), 6),
+        ("mode", format!("{:o}", member.mode), 8),
+        ("size", size.to_string(), 10),
+    ];
+    if let Some((field_name, value, _)) =
+        fields.iter().find(|(_, value, width)| value.len() > *width)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} of '{}' ({}) does not fit in an ar header",
+                field_name, member.name, value
+            ),
+        ));
+    }
+    let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+        name, fields[0].1, fields[1].1, fields[2].1, fields[3].1, fields[4].1
+    );
+    ar_buffer.extend_from_slice(header.as_bytes());
+    Ok(())
+}
+pub fn write_ar(members: &[ArMember], variant: ArVariant) -> Result<Vec<u8>, io::Error> {
+    let mut ar_buffer = AR_MAGIC.to_vec();
+
+    let mut long_names = Vec::new();
+    let mut long_name_offsets = Vec::new();
+    if variant == ArVariant::Gnu {
+        for member in members {
+            if member.name.len() > 15 || member.name.contains(' ') {
+                long_name_offsets.push(Some(long_names.len()));
+                long_names.extend_from_slice(member.name.as_bytes());
+                long_names.extend_from_slice(b"/\n");
+            } else {
+                long_name_offsets.push(None);
+            }
+        }
+        if !long_names.is_empty() {
+            let header = format!("{:<48}{:<10}`\n", "//", long_names.len());
+            ar_buffer.extend_from_slice(header.as_bytes());
+            ar_buffer.extend_from_slice(&long_names);
+            if long_names.len() % 2 == 1 {
+                ar_buffer.push(b'\n');
+            }
+        }
+    }
+
+    for (index, member) in members.iter().enumerate() {
+        match variant {
+            ArVariant::Gnu => {
+                let name = match long_name_offsets[index] {
+                    Some(offset) => format!("/{}", offset),
+                    None => format!("{}/", member.name),
+                };
+                write_header(&mut ar_buffer, &name, member, member.data.len())?;
+                ar_buffer.extend_from_slice(&member.data);
+            }
+            ArVariant::Bsd => {
+                if member.name.len() > 16 || member.name.contains(' ') {
+                    let name = format!("#1/{}", member.name.len());
+                    let size = member.name.len() + member.data.len();
+                    write_header(&mut ar_buffer, &name, member, size)?;
+                    ar_buffer.extend_from_slice(member.name.as_bytes());
+                } else {
+                    write_header(&mut ar_buffer, &member.name, member, member.data.len())?;
+                }
+                ar_buffer.extend_from_slice(&member.data);
+            }
+        }
+        if ar_buffer.len() % 2 == 1 {
+            ar_buffer.push(b'\n');
+        }
+    }
+    Ok(ar_buffer)
+}
+pub fn ar_entries(members: Vec<ArMember>) -> Vec<Entry> {
+    members
+        .into_iter()
+        .map(|member| {
+            let mut entry = Entry::new(&member.name, EntryKind::File);
+            entry.mode = member.mode & 0o7777;
+            entry.uid = member.uid;
+            entry.gid = member.gid;
+            entry.mtime = member.mtime;
+            entry.data = member.data;
+            entry
+        })
+        .collect()
+}
+pub fn entries_to_ar(entries: &[Entry]) -> Vec<ArMember> {
+    let mut members = Vec::new();
+    for entry in entries {
+        match entry.kind {
+            EntryKind::File if !entry.name.contains('/') => members.push(ArMember {
+                name: entry.name.clone(),
+                mtime: entry.mtime,
+                uid: entry.uid,
+                gid: entry.gid,
+                mode: EntryKind::File.file_type_bits() | entry.mode,
+                data: entry.data.clone(),
+            }),
+            EntryKind::File => println!(
+                "Warning: {}: ar archives are flat, members inside directories are skipped",
+                entry.name
+            ),
+            EntryKind::Directory => {}
+            _ => println!(
+                "Warning: {}: ar archives only hold regular files, skipped",
+                entry.name
+            ),
+        }
+    }
+    members
+}
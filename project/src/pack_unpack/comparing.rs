@@ -0,0 +1,387 @@
+use crate::pack_unpack::digests::parse_pax_records;
+use crate::pack_unpack::unpacking::{
+    open_archive, or_truncated, read_block, truncated_member, UStarHeader,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+fn type_matches(type_flag: char, metadata: &Metadata) -> bool {
+    let file_type = metadata.file_type();
+    match type_flag {
+        '0' | '\0' | '1' | '7' => file_type.is_file(),
+        '2' => file_type.is_symlink(),
+        '3' => file_type.is_char_device(),
+        '4' => file_type.is_block_device(),
+        '5' => file_type.is_dir(),
+        '6' => file_type.is_fifo(),
+        _ => true,
+    }
+}
+/// The name, link target and data size of a member, with the pax records before it applied.
+struct MemberNames {
+    name: String,
+    link_name: String,
+    size: usize,
+}
+fn member_names(header: &UStarHeader, pax_records: &mut HashMap<String, String>) -> MemberNames {
+    let size = match pax_records
+        .remove("size")
+        .and_then(|size| size.parse().ok())
+    {
+        Some(size) if header.data_size() > 0 => size,
+        _ => header.data_size(),
+    };
+    let names = MemberNames {
+        name: pax_records
+            .remove("path")
+            .unwrap_or_else(|| header.file_name()),
+        link_name: pax_records
+            .remove("linkpath")
+            .unwrap_or_else(|| header.link_name()),
+        size,
+    };
+    pax_records.clear();
+    names
+}
+/// Reads the pax records of an `x` header; `g` headers are skipped.
+fn read_pax_header(
+    header: &UStarHeader,
+    reader: &mut dyn Read,
+    pax_records: &mut HashMap<String, String>,
+) -> Result<(), io::Error> {
+    let size = header.data_size();
+    let mut content = vec![0; size + (512 - (size % 512)) % 512];
+    reader
+        .read_exact(&mut content)
+        .map_err(|e| or_truncated(e, &header.file_name()))?;
+    if header.type_flag() == 'x' {
+        *pax_records = parse_pax_records(&content[..size]);
+    }
+    Ok(())
+}
+fn skip_data(reader: &mut dyn Read, name: &str, length: usize) -> Result<(), io::Error> {
+    if io::copy(&mut reader.take(length as u64), &mut io::sink())? < length as u64 {
+        return Err(truncated_member(name));
+    }
+    Ok(())
+}
+fn missing_end_marker() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "end-of-archive marker is missing, the archive is truncated",
+    )
+}
+fn contents_match(reader: &mut dyn Read, path: &Path, size: usize) -> Result<bool, io::Error> {
+    let mut file = File::open(path)?;
+    let mut archived = vec![0; 64 * 1024];
+    let mut on_disk = vec![0; 64 * 1024];
+    let mut remaining = size;
+    let mut matches = true;
+
+    while remaining > 0 {
+        let chunk = remaining.min(archived.len());
+        reader.read_exact(&mut archived[..chunk])?;
+        if matches {
+            let mut filled = 0;
+            while filled < chunk {
+                let read = file.read(&mut on_disk[filled..chunk])?;
+                if read == 0 {
+                    break;
+                }
+                filled += read;
+            }
+            matches = filled == chunk && archived[..chunk] == on_disk[..chunk];
+        }
+        remaining -= chunk;
+    }
+    Ok(matches)
+}
+fn compare_entry(
+    header: &UStarHeader,
+    names: &MemberNames,
+    reader: &mut dyn Read,
+    directory: &Path,
+) -> Result<Vec<String>, io::Error> {
+    let type_flag = header.type_flag();
+    let path = directory.join(&names.name);
+    let size = names.size;
+    let padding = (512 - (size % 512)) % 512;
+    let mut differences = Vec::new();
+
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            skip_data(reader, &names.name, size + padding)?;
+            differences.push("does not exist".to_string());
+            return Ok(differences);
+        }
+    };
+
+    if !type_matches(type_flag, &metadata) {
+        skip_data(reader, &names.name, size + padding)?;
+        differences.push("file type differs".to_string());
+        return Ok(differences);
+    }
+
+    if metadata.mode() & 0o777 != header.mode_bits() & 0o777 && type_flag != '2' {
+        differences.push(format!(
+            "mode differs ({:o} in archive, {:o} on disk)",
+            header.mode_bits() & 0o777,
+            metadata.mode() & 0o777
+        ));
+    }
+    if metadata.uid() != header.uid() {
+        differences.push(format!(
+            "uid differs ({} in archive, {} on disk)",
+            header.uid(),
+            metadata.uid()
+        ));
+    }
+    if metadata.gid() != header.gid() {
+        differences.push(format!(
+            "gid differs ({} in archive, {} on disk)",
+            header.gid(),
+            metadata.gid()
+        ));
+    }
+    if metadata.mtime() != header.modification_time() && type_flag != '5' {
+        differences.push(format!(
+            "mtime differs ({} in archive, {} on disk)",
+            header.modification_time(),
+            metadata.mtime()
+        ));
+    }
+
+    match type_flag {
+        '0' | '\0' | '7' => {
+            if metadata.len() != size as u64 {
+                differences.push(format!(
+                    "size differs ({} in archive, {} on disk)",
+                    size,
+                    metadata.len()
+                ));
+                skip_data(reader, &names.name, size)?;
+            } else if !contents_match(reader, &path, size)
+                .map_err(|e| or_truncated(e, &names.name))?
+            {
+                differences.push("contents differ".to_string());
+            }
+        }
+        '1' => {
+            let target = directory.join(&names.link_name);
+            match fs::symlink_metadata(&target) {
+                Ok(target_metadata)
+                    if target_metadata.dev() == metadata.dev()
+                        && target_metadata.ino() == metadata.ino() => {}
+                _ => differences.push(format!("not linked to {}", names.link_name)),
+            }
+        }
+        '2' => {
+            let target = fs::read_link(&path)?;
+            if target.to_string_lossy() != names.link_name {
+                differences.push(format!(
+                    "symlink target differs ({} in archive, {} on disk)",
+                    names.link_name,
+                    target.display()
+                ));
+            }
+        }
+        '3' | '4' => {
+            let (major, minor) = header.device_numbers();
+            if (major << 8) | minor != metadata.rdev() {
+                differences.push("device numbers differ".to_string());
+            }
+        }
+        _ => skip_data(reader, &names.name, size)?,
+    }
+    skip_data(reader, &names.name, padding)?;
+
+    Ok(differences)
+}
+pub fn compare_with_filesystem(tar_file: &str, directory: &str) -> Result<usize, io::Error> {
+    let mut reader = open_archive(tar_file)?;
+    let directory = Path::new(directory);
+    let mut buffer = vec![0; 512];
+    let mut pax_records = HashMap::new();
+    let mut differing = 0;
+
+    loop {
+        if !read_block(&mut reader, &mut buffer)? {
+            return Err(missing_end_marker());
+        }
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        let header = UStarHeader::from_bytes(&buffer);
+        if header.type_flag() == 'x' || header.type_flag() == 'g' {
+            read_pax_header(&header, &mut reader, &mut pax_records)?;
+            continue;
+        }
+        let names = member_names(&header, &mut pax_records);
+        let differences = compare_entry(&header, &names, &mut reader, directory)?;
+        if differences.is_empty() {
+            println!("{}: OK", names.name);
+        } else {
+            differing += 1;
+            for difference in differences {
+                println!("{}: {}", names.name, difference);
+            }
+        }
+    }
+    Ok(differing)
+}
+struct MemberSummary {
+    type_flag: char,
+    size: usize,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    user_name: String,
+    group_name: String,
+    modification_time: i64,
+    link_name: String,
+    digest: String,
+}
+fn summarize_archive(tar_file: &str) -> Result<BTreeMap<String, MemberSummary>, io::Error> {
+    let mut reader = open_archive(tar_file)?;
+    let mut buffer = vec![0; 512];
+    let mut pax_records = HashMap::new();
+    let mut members = BTreeMap::new();
+
+    loop {
+        if !read_block(&mut reader, &mut buffer)? {
+            return Err(missing_end_marker());
+        }
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        let header = UStarHeader::from_bytes(&buffer);
+        if header.type_flag() == 'x' || header.type_flag() == 'g' {
+            read_pax_header(&header, &mut reader, &mut pax_records)?;
+            continue;
+        }
+        let names = member_names(&header, &mut pax_records);
+        let padding = (512 - (names.size % 512)) % 512;
+
+        let mut hasher = Sha256::new();
+        if io::copy(&mut (&mut reader).take(names.size as u64), &mut hasher)? < names.size as u64 {
+            return Err(truncated_member(&names.name));
+        }
+        skip_data(&mut reader, &names.name, padding)?;
+        let digest = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        members.insert(
+            names.name,
+            MemberSummary {
+                type_flag: header.type_flag(),
+                size: names.size,
+                mode: header.mode_bits(),
+                uid: header.uid(),
+                gid: header.gid(),
+                user_name: header.user_name(),
+                group_name: header.group_name(),
+                modification_time: header.modification_time(),
+                link_name: names.link_name,
+                digest,
+            },
+        );
+    }
+    Ok(members)
+}
+fn member_changes(old: &MemberSummary, new: &MemberSummary) -> Vec<String> {
+    let mut changes = Vec::new();
+    if old.type_flag != new.type_flag {
+        changes.push(format!("type {} -> {}", old.type_flag, new.type_flag));
+    }
+    if old.size != new.size || old.digest != new.digest {
+        changes.push(format!(
+            "content sha256:{} -> sha256:{}",
+            &old.digest[..12],
+            &new.digest[..12]
+        ));
+    }
+    if old.mode != new.mode {
+        changes.push(format!("mode {:o} -> {:o}", old.mode, new.mode));
+    }
+    if old.uid != new.uid || old.gid != new.gid {
+        changes.push(format!(
+            "owner {}:{} -> {}:{}",
+            old.uid, old.gid, new.uid, new.gid
+        ));
+    }
+    if old.user_name != new.user_name || old.group_name != new.group_name {
+        changes.push(format!(
+            "owner names {}:{} -> {}:{}",
+            old.user_name, old.group_name, new.user_name, new.group_name
+        ));
+    }
+    if old.modification_time != new.modification_time {
+        changes.push(format!(
+            "mtime {} -> {}",
+            old.modification_time, new.modification_time
+        ));
+    }
+    if old.link_name != new.link_name {
+        changes.push(format!("link {} -> {}", old.link_name, new.link_name));
+    }
+    changes
+}
+pub fn compare_archives(
+    old_tar_file: &str,
+    new_tar_file: &str,
+    as_json: bool,
+) -> Result<usize, io::Error> {
+    let old_members = summarize_archive(old_tar_file)?;
+    let new_members = summarize_archive(new_tar_file)?;
+
+    let removed: Vec<String> = old_members
+        .keys()
+        .filter(|name| !new_members.contains_key(*name))
+        .cloned()
+        .collect();
+    let added: Vec<String> = new_members
+        .keys()
+        .filter(|name| !old_members.contains_key(*name))
+        .cloned()
+        .collect();
+    let mut changed: Vec<(String, Vec<String>)> = Vec::new();
+    for (name, old) in &old_members {
+        if let Some(new) = new_members.get(name) {
+            let changes = member_changes(old, new);
+            if !changes.is_empty() {
+                changed.push((name.clone(), changes));
+            }
+        }
+    }
+
+    if as_json {
+        let changed_json: Vec<Value> = changed
+            .iter()
+            .map(|(name, changes)| json!({ "name": name, "changes": changes }))
+            .collect();
+        println!(
+            "{}",
+            json!({ "added": added, "removed": removed, "changed": changed_json })
+        );
+    } else {
+        for name in &added {
+            println!("+ {}", name);
+        }
+        for name in &removed {
+            println!("- {}", name);
+        }
+        for (name, changes) in &changed {
+            println!("~ {}: {}", name, changes.join(", "));
+        }
+    }
+    Ok(added.len() + removed.len() + changed.len())
+}
@@ -0,0 +1,90 @@
+use crate::pack_unpack::ar::{ar_entries, entries_to_ar, is_ar, read_ar, write_ar, ArVariant};
+use crate::pack_unpack::cpio::{is_cpio, read_cpio_entries, write_cpio_entries, CpioFormat};
+use crate::pack_unpack::entry::{read_tar_entries, write_tar_entries, Entry};
+use crate::pack_unpack::unpacking::open_archive;
+use crate::pack_unpack::zip::{is_zip, read_zip_entries, write_zip_entries};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ArchiveFormat {
+    #[default]
+    Tar,
+    TarGz,
+    Zip,
+    Cpio,
+    CpioOdc,
+    Ar,
+}
+impl ArchiveFormat {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if file_name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if file_name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if file_name.ends_with(".cpio") {
+            Some(ArchiveFormat::Cpio)
+        } else if file_name.ends_with(".a") || file_name.ends_with(".ar") {
+            Some(ArchiveFormat::Ar)
+        } else {
+            None
+        }
+    }
+}
+pub fn read_entries(archive_file: &str) -> Result<Vec<Entry>, io::Error> {
+    if is_zip(archive_file)? {
+        return read_zip_entries(&mut BufReader::new(File::open(archive_file)?));
+    }
+    let mut reader = BufReader::new(open_archive(archive_file)?);
+    if is_ar(reader.fill_buf()?) {
+        Ok(ar_entries(read_ar(&mut reader)?))
+    } else if is_cpio(reader.fill_buf()?) {
+        read_cpio_entries(&mut reader)
+    } else {
+        read_tar_entries(&mut reader)
+    }
+}
+pub fn write_entries(
+    entries: &[Entry],
+    archive_file: &str,
+    format: ArchiveFormat,
+) -> Result<(), io::Error> {
+    let mut output = BufWriter::new(File::create(archive_file)?);
+    match format {
+        ArchiveFormat::Tar => output.write_all(&write_tar_entries(entries)?)?,
+        ArchiveFormat::TarGz => {
+            let mut encoder = GzEncoder::new(&mut output, Compression::default());
+            encoder.write_all(&write_tar_entries(entries)?)?;
+            encoder.finish()?;
+        }
+        ArchiveFormat::Zip => {
+            write_zip_entries(entries, &mut output)?;
+        }
+        ArchiveFormat::Cpio => output.write_all(&write_cpio_entries(entries, CpioFormat::Newc)?)?,
+        ArchiveFormat::CpioOdc => {
+            output.write_all(&write_cpio_entries(entries, CpioFormat::Odc)?)?
+        }
+        ArchiveFormat::Ar => {
+            output.write_all(&write_ar(&entries_to_ar(entries), ArVariant::Gnu)?)?
+        }
+    }
+    output.flush()
+}
+pub fn convert_archive(input_file: &str, output_file: &str) -> Result<usize, io::Error> {
+    let format = ArchiveFormat::from_file_name(output_file).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "cannot tell the format of {}, use .tar, .tar.gz, .zip, .cpio or .a",
+                output_file
+            ),
+        )
+    })?;
+    let entries = read_entries(input_file)?;
+    write_entries(&entries, output_file, format)?;
+    Ok(entries.len())
+}
@@ -0,0 +1,347 @@
+use crate::pack_unpack::entry::{Entry, EntryKind};
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+pub const NEWC_MAGIC: &[u8; 6] = b"070701";
+const NEWC_CRC_MAGIC: &[u8; 6] = b"070702";
+const ODC_MAGIC: &[u8; 6] = b"070707";
+const TRAILER_NAME: &str = "TRAILER!!!";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CpioFormat {
+    #[default]
+    Newc,
+    Odc,
+}
+pub fn is_cpio(start: &[u8]) -> bool {
+    start.starts_with(NEWC_MAGIC)
+        || start.starts_with(NEWC_CRC_MAGIC)
+        || start.starts_with(ODC_MAGIC)
+}
+#[derive(Debug, Default)]
+struct CpioHeader {
+    inode: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    links: u32,
+    mtime: i64,
+    size: usize,
+    device: (u64, u64),
+    rdev: (u64, u64),
+}
+fn pad_to_four(buffer: &mut Vec<u8>) {
+    buffer.resize(buffer.len().div_ceil(4) * 4, 0);
+}
+fn write_header(
+    cpio_buffer: &mut Vec<u8>,
+    header: &CpioHeader,
+    name: &str,
+    format: CpioFormat,
+) -> Result<(), io::Error> {
+    match format {
+        CpioFormat::Newc => {
+            let fields = [
+                ("inode", header.inode),
+                ("mode", header.mode as u64),
+                ("uid", header.uid as u64),
+                ("gid", header.gid as u64),
+                ("link count", header.links as u64),
+                ("mtime", header.mtime.max(0) as u64),
+                ("size", header.size as u64),
+                ("device major", header.device.0),
+                ("device minor", header.device.1),
+                ("rdev major", header.rdev.0),
+                ("rdev minor", header.rdev.1),
+                ("name length", name.len() as u64 + 1),
+                ("checksum", 0),
+            ];
+            cpio_buffer.extend_from_slice(NEWC_MAGIC);
+            for (field_name, field) in fields {
+                let field = u32::try_from(field).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} of '{}' ({}) does not fit in a newc cpio header",
+                            field_name, name, field
+                        ),
+                    )
+                })?;
+                cpio_buffer.extend_from_slice(format!("{:08x}", field).as_bytes());
+            }
+        }
+        CpioFormat::Odc => {
+            cpio_buffer.extend_from_slice(ODC_MAGIC);
+            let fields = [
+                (header.device.0 << 8) | header.device.1,
+                header.inode,
+                header.mode as u64,
+                header.uid as u64,
+                header.gid as u64,
+                header.links as u64,
+                // Device numbers are combined the same way create_header splits them.
+                (header.rdev.0 << 8) | header.rdev.1,
+            ];
+            for field in fields {
+                cpio_buffer.extend_from_slice(format!("{:06o}", field & 0o777777).as_bytes());
+            }
+            cpio_buffer.extend_from_slice(
+                format!("{:011o}", header.mtime.max(0) as u64 & 0o77777777777).as_bytes(),
+            );
+            cpio_buffer.extend_from_slice(format!("{:06o}", name.len() + 1).as_bytes());
+            cpio_buffer.extend_from_slice(format!("{:011o}", header.size).as_bytes());
+        }
+    }
+    cpio_buffer.extend_from_slice(name.as_bytes());
+    cpio_buffer.push(0);
+    if format == CpioFormat::Newc {
+        pad_to_four(cpio_buffer);
+    }
+    Ok(())
+}
+pub fn write_cpio_entries(entries: &[Entry], format: CpioFormat) -> Result<Vec<u8>, io::Error> {
+    if entries
+        .iter()
+        .any(|entry| !entry.user_name.is_empty() || !entry.group_name.is_empty())
+    {
+        println!("Warning: cpio archives only store numeric owners, user and group names are lost");
+    }
+    if format == CpioFormat::Odc && entries.iter().any(|entry| entry.data.len() > 0o77777777777) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "files of 8 GiB or more do not fit in an odc cpio archive",
+        ));
+    }
+
+    // Hard links share an inode. newc stores the data with the last of them only,
+    // odc repeats it for every link the way GNU cpio does.
+    let mut links: HashMap<&str, u32> = HashMap::new();
+    let mut last_link: HashMap<&str, usize> = HashMap::new();
+    let mut group_data: HashMap<&str, &[u8]> = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        match entry.kind {
+            EntryKind::File => {
+                links.insert(&entry.name, 1);
+                group_data.insert(&entry.name, &entry.data);
+                last_link.insert(&entry.name, index);
+            }
+            EntryKind::HardLink => {
+                if let Some(count) = links.get_mut(entry.link_name.as_str()) {
+                    *count += 1;
+                    last_link.insert(&entry.link_name, index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut cpio_buffer = Vec::new();
+    let mut inodes: HashMap<&str, u64> = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let mut header = CpioHeader {
+            inode: index as u64 + 1,
+            mode: entry.kind.file_type_bits() | entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            links: if entry.kind == EntryKind::Directory {
+                2
+            } else {
+                1
+            },
+            mtime: entry.mtime,
+            ..CpioHeader::default()
+        };
+        let data: &[u8] = match entry.kind {
+            EntryKind::File | EntryKind::HardLink => {
+                let group = if entry.kind == EntryKind::File {
+                    inodes.insert(&entry.name, header.inode);
+                    entry.name.as_str()
+                } else {
+                    match inodes.get(entry.link_name.as_str()) {
+                        Some(&inode) => {
+                            header.inode = inode;
+                            entry.link_name.as_str()
+                        }
+                        None => {
+                            println!(
+                                "Warning: {}: hard link target {} is not in the archive, skipped",
+                                entry.name, entry.link_name
+                            );
+                            continue;
+                        }
+                    }
+                };
+                header.links = links[group];
+                if format == CpioFormat::Odc || last_link[group] == index {
+                    group_data[group]
+                } else {
+                    &[]
+                }
+            }
+            EntryKind::Symlink => entry.link_name.as_bytes(),
+            EntryKind::CharDevice | EntryKind::BlockDevice => {
+                header.rdev = entry.device;
+                &[]
+            }
+            EntryKind::Directory | EntryKind::Fifo => &[],
+        };
+        header.size = data.len();
+
+        write_header(&mut cpio_buffer, &header, &entry.name, format)?;
+        cpio_buffer.extend_from_slice(data);
+        if format == CpioFormat::Newc {
+            pad_to_four(&mut cpio_buffer);
+        }
+    }
+
+    let trailer = CpioHeader {
+        links: 1,
+        ..CpioHeader::default()
+    };
+    write_header(&mut cpio_buffer, &trailer, TRAILER_NAME, format)?;
+    cpio_buffer.resize(cpio_buffer.len().div_ceil(512) * 512, 0);
+    Ok(cpio_buffer)
+}
+fn read_padding(reader: &mut dyn Read, position: &mut usize) -> Result<(), io::Error> {
+    let padding = position.div_ceil(4) * 4 - *position;
+    reader.read_exact(&mut vec![0; padding])?;
+    *position += padding;
+    Ok(())
+}
+fn number_field(field: &[u8], radix: u32) -> Result<u64, io::Error> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|text| u64::from_str_radix(text, radix).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid cpio header field"))
+}
+fn read_header(
+    reader: &mut dyn Read,
+    position: &mut usize,
+) -> Result<(CpioHeader, String, CpioFormat), io::Error> {
+    let truncated = |_| {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "cpio archive is truncated, the TRAILER!!! record is missing",
+        )
+    };
+    let mut magic = [0u8; 6];
+    reader.read_exact(&mut magic).map_err(truncated)?;
+
+    let (header, name_size, format) = if &magic == NEWC_MAGIC || &magic == NEWC_CRC_MAGIC {
+        let mut fields = [0u8; 104];
+        reader.read_exact(&mut fields).map_err(truncated)?;
+        let field = |index: usize| number_field(&fields[index * 8..(index + 1) * 8], 16);
+        let header = CpioHeader {
+            inode: field(0)?,
+            mode: field(1)? as u32,
+            uid: field(2)? as u32,
+            gid: field(3)? as u32,
+            links: field(4)? as u32,
+            mtime: field(5)? as i64,
+            size: field(6)? as usize,
+            device: (field(7)?, field(8)?),
+            rdev: (field(9)?, field(10)?),
+        };
+        *position += 110;
+        (header, field(11)? as usize, CpioFormat::Newc)
+    } else if &magic == ODC_MAGIC {
+        let mut fields = [0u8; 70];
+        reader.read_exact(&mut fields).map_err(truncated)?;
+        let field = |start: usize, len: usize| number_field(&fields[start..start + len], 8);
+        let device = field(0, 6)?;
+        let rdev = field(36, 6)?;
+        let header = CpioHeader {
+            inode: field(6, 6)?,
+            mode: field(12, 6)? as u32,
+            uid: field(18, 6)? as u32,
+            gid: field(24, 6)? as u32,
+            links: field(30, 6)? as u32,
+            mtime: field(42, 11)? as i64,
+            size: field(59, 11)? as usize,
+            device: (device >> 8, device & 0xff),
+            rdev: (rdev >> 8, rdev & 0xff),
+        };
+        *position += 76;
+        (header, field(53, 6)? as usize, CpioFormat::Odc)
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad cpio header magic, only the newc and odc formats are supported",
+        ));
+    };
+
+    let mut name = vec![0; name_size];
+    reader.read_exact(&mut name).map_err(truncated)?;
+    *position += name_size;
+    if format == CpioFormat::Newc {
+        read_padding(reader, position)?;
+    }
+    let name = String::from_utf8_lossy(&name)
+        .trim_end_matches('\0')
+        .to_string();
+    Ok((header, name, format))
+}
+pub fn read_cpio_entries(reader: &mut dyn Read) -> Result<Vec<Entry>, io::Error> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut first_links: HashMap<(u64, u64, u64), usize> = HashMap::new();
+    let mut position = 0;
+
+    loop {
+        let (header, name, format) = read_header(reader, &mut position)?;
+        let mut data = vec![0; header.size];
+        reader.read_exact(&mut data).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("cpio archive is truncated inside '{}'", name),
+            )
+        })?;
+        position += header.size;
+        if format == CpioFormat::Newc {
+            read_padding(reader, &mut position)?;
+        }
+
+        if name == TRAILER_NAME {
+            break;
+        }
+        let kind = match EntryKind::from_file_type_bits(header.mode) {
+            Some(kind) => kind,
+            None => {
+                println!(
+                    "Warning: {}: unknown file type {:o}, skipped",
+                    name, header.mode
+                );
+                continue;
+            }
+        };
+
+        let mut entry = Entry::new(&name, kind);
+        entry.mode = header.mode & 0o7777;
+        entry.uid = header.uid;
+        entry.gid = header.gid;
+        entry.mtime = header.mtime;
+        match kind {
+            EntryKind::Symlink => entry.link_name = String::from_utf8_lossy(&data).to_string(),
+            EntryKind::CharDevice | EntryKind::BlockDevice => entry.device = header.rdev,
+            EntryKind::File if header.links > 1 => {
+                let key = (header.device.0, header.device.1, header.inode);
+                match first_links.get(&key) {
+                    Some(&first) => {
+                        if !data.is_empty() {
+                            entries[first].data = data;
+                        }
+                        entry.kind = EntryKind::HardLink;
+                        entry.link_name = entries[first].name.clone();
+                    }
+                    None => {
+                        first_links.insert(key, entries.len());
+                        entry.data = data;
+                    }
+                }
+            }
+            EntryKind::File => entry.data = data,
+            _ => {}
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
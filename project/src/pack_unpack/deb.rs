@@ -0,0 +1,127 @@
+use crate::pack_unpack::ar::{read_ar, write_ar, ArMember, ArVariant};
+use crate::pack_unpack::entry::{read_tar_entries, write_tar_entries, Entry, EntryKind};
+use crate::pack_unpack::packing::{create_archive, PackOptions};
+use crate::pack_unpack::unpacking::{decompress, extract_entries, UnpackOptions};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEB_VERSION: &[u8] = b"2.0\n";
+
+fn read_deb(deb_file: &str) -> Result<Vec<ArMember>, io::Error> {
+    let members = read_ar(&mut BufReader::new(File::open(deb_file)?))?;
+    if members.first().map(|member| member.name.as_str()) != Some("debian-binary") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is not a Debian package, debian-binary is missing",
+                deb_file
+            ),
+        ));
+    }
+    Ok(members)
+}
+fn inner_entries(member: &ArMember) -> Result<Vec<Entry>, io::Error> {
+    read_tar_entries(&mut decompress(Box::new(member.data.as_slice()))?)
+}
+fn is_inner_tarball(member: &ArMember, prefix: &str) -> bool {
+    member.name.starts_with(prefix)
+}
+pub fn list_deb(deb_file: &str) -> Result<usize, io::Error> {
+    let members = read_deb(deb_file)?;
+    let mut listed = 0;
+    for member in &members {
+        println!("{} ({} bytes)", member.name, member.data.len());
+        if is_inner_tarball(member, "control.tar") || is_inner_tarball(member, "data.tar") {
+            for entry in inner_entries(member)? {
+                println!("    {}", entry.name);
+                listed += 1;
+            }
+        }
+    }
+    Ok(listed)
+}
+pub fn extract_deb(deb_file: &str, directory: &str) -> Result<(), io::Error> {
+    let members = read_deb(deb_file)?;
+    // Laid out the way dpkg-deb -R does: data at the top, control files under DEBIAN.
+    for (prefix, destination) in [
+        ("data.tar", directory.to_string()),
+        ("control.tar", format!("{}/DEBIAN", directory)),
+    ] {
+        let member = members
+            .iter()
+            .find(|member| is_inner_tarball(member, prefix))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} has no {}.* member", deb_file, prefix),
+                )
+            })?;
+        fs::create_dir_all(&destination)?;
+        let options = UnpackOptions {
+            destination: Some(destination),
+            ..UnpackOptions::default()
+        };
+        extract_entries(inner_entries(member)?, &options)?;
+    }
+    Ok(())
+}
+fn relocate(name: &str, top: &str) -> String {
+    match name.strip_prefix(top) {
+        Some("") => ".".to_string(),
+        Some(rest) if rest.starts_with('/') => format!(".{}", rest),
+        _ => name.to_string(),
+    }
+}
+fn pack_tree(directory: &str, temporary_name: &str) -> Result<Vec<u8>, io::Error> {
+    let tar_file = create_archive(directory, temporary_name, &PackOptions::default())?;
+    let entries = read_tar_entries(&mut BufReader::new(File::open(&tar_file)?));
+    fs::remove_file(&tar_file)?;
+    let mut entries = entries?;
+
+    // create_archive stores names under the directory itself, dpkg expects them under "./"
+    // and owned by root.
+    let top = entries
+        .first()
+        .map(|entry| entry.name.clone())
+        .unwrap_or_default();
+    for entry in &mut entries {
+        entry.name = relocate(&entry.name, &top);
+        if entry.kind == EntryKind::HardLink {
+            entry.link_name = relocate(&entry.link_name, &top);
+        }
+        entry.uid = 0;
+        entry.gid = 0;
+        entry.user_name = "root".to_string();
+        entry.group_name = "root".to_string();
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&write_tar_entries(&entries)?)?;
+    encoder.finish()
+}
+pub fn build_deb(control_dir: &str, data_dir: &str, deb_file: &str) -> Result<(), io::Error> {
+    if !Path::new(control_dir).join("control").is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no control file", control_dir),
+        ));
+    }
+    let control = pack_tree(control_dir, &format!("{}.control", deb_file))?;
+    let data = pack_tree(data_dir, &format!("{}.data", deb_file))?;
+
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0);
+    let members = [
+        ArMember::new("debian-binary", DEB_VERSION.to_vec(), mtime),
+        ArMember::new("control.tar.gz", control, mtime),
+        ArMember::new("data.tar.gz", data, mtime),
+    ];
+    // Written like dpkg-deb does, plain member names without the GNU trailing slash.
+    fs::write(deb_file, write_ar(&members, ArVariant::Bsd)?)
+}
@@ -0,0 +1,198 @@
+use crate::pack_unpack::patterns::member_matches;
+use crate::pack_unpack::spooling::SkippedFiles;
+use crate::pack_unpack::unpacking::{
+    is_encrypted_archive, is_gzip, open_archive, or_truncated, read_block, truncated_member,
+    UStarHeader,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::{fs, io};
+
+fn is_selected(patterns: &[&str], name: &str) -> bool {
+    patterns.iter().any(|pattern| member_matches(pattern, name))
+}
+fn padding_for(size: usize) -> usize {
+    (512 - (size % 512)) % 512
+}
+fn missing_end_marker() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "end-of-archive marker is missing, the archive is truncated",
+    )
+}
+/// Copies a member's data and padding, failing rather than writing a short member.
+fn copy_member_data(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    name: &str,
+    length: usize,
+) -> Result<(), io::Error> {
+    if io::copy(&mut reader.take(length as u64), writer)? < length as u64 {
+        return Err(truncated_member(name));
+    }
+    Ok(())
+}
+fn find_orphaned_targets(tar_file: &str, patterns: &[&str]) -> Result<HashSet<String>, io::Error> {
+    let mut reader = open_archive(tar_file)?;
+    let mut buffer = vec![0; 512];
+    let mut removed_names = HashSet::new();
+    let mut targets = HashSet::new();
+
+    loop {
+        if !read_block(&mut reader, &mut buffer)? {
+            return Err(missing_end_marker());
+        }
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        let header = UStarHeader::from_bytes(&buffer);
+        let name = header.file_name();
+        if is_selected(patterns, &name) {
+            removed_names.insert(name);
+        } else if header.type_flag() == '1' {
+            // Only a link that comes after its removed target can take over the data, so
+            // nothing else has to be kept while filtering.
+            let target = header.link_name();
+            if removed_names.contains(&target) {
+                targets.insert(target);
+            }
+        }
+        let size = header.data_size();
+        copy_member_data(
+            &mut reader,
+            &mut io::sink(),
+            &header.file_name(),
+            size + padding_for(size),
+        )?;
+    }
+    Ok(targets)
+}
+fn filter_members(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    patterns: &[&str],
+    orphaned_targets: &HashSet<String>,
+) -> Result<(usize, u64), io::Error> {
+    let mut buffer = vec![0; 512];
+    let mut orphaned_data = SkippedFiles::default();
+    let mut promoted: HashMap<String, String> = HashMap::new();
+    let mut pending_pax: Vec<u8> = Vec::new();
+    let mut removed = 0;
+    let mut written: u64 = 0;
+
+    loop {
+        if !read_block(reader, &mut buffer)? {
+            return Err(missing_end_marker());
+        }
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        let mut header = UStarHeader::from_bytes(&buffer);
+        let name = header.file_name();
+        let size = header.data_size();
+        let padding = padding_for(size);
+
+        if header.type_flag() == 'x' {
+            pending_pax = buffer.clone();
+            pending_pax.resize(512 + size + padding, 0);
+            reader
+                .read_exact(&mut pending_pax[512..])
+                .map_err(|e| or_truncated(e, &name))?;
+            continue;
+        }
+        if is_selected(patterns, &name) {
+            pending_pax.clear();
+            removed += 1;
+            if orphaned_targets.contains(&name) {
+                let kept = orphaned_data.insert(name.clone(), &mut reader.take(size as u64))?;
+                if kept < size as u64 {
+                    return Err(truncated_member(&name));
+                }
+                copy_member_data(reader, &mut io::sink(), &name, padding)?;
+            } else {
+                copy_member_data(reader, &mut io::sink(), &name, size + padding)?;
+            }
+            continue;
+        }
+
+        writer.write_all(&pending_pax)?;
+        written += pending_pax.len() as u64;
+        pending_pax.clear();
+
+        if header.type_flag() == '1' {
+            let target = header.link_name();
+            if let Some(content_size) = orphaned_data.size(&target) {
+                let content_size = content_size as usize;
+                header.make_regular_file(content_size);
+                header.update_checksum();
+                writer.write_all(&header.as_bytes())?;
+                orphaned_data.write_to(&target, writer)?;
+                writer.write_all(&vec![0; padding_for(content_size)])?;
+                written += (512 + content_size + padding_for(content_size)) as u64;
+                promoted.insert(target, name);
+                continue;
+            }
+            if let Some(new_target) = promoted.get(&target) {
+                header.set_link_name(new_target);
+                header.update_checksum();
+            }
+        }
+
+        writer.write_all(&header.as_bytes())?;
+        copy_member_data(reader, writer, &name, size + padding)?;
+        written += (512 + size + padding) as u64;
+    }
+
+    writer.write_all(&[0; 1024])?;
+    written += 1024;
+    Ok((removed, written))
+}
+fn write_compressed(
+    reader: &mut dyn Read,
+    temp_name: &str,
+    patterns: &[&str],
+    orphaned_targets: &HashSet<String>,
+) -> Result<usize, io::Error> {
+    let temp_file = File::create(temp_name)?;
+    let mut encoder = GzEncoder::new(BufWriter::new(temp_file), Compression::default());
+    let (removed, _) = filter_members(reader, &mut encoder, patterns, orphaned_targets)?;
+    encoder.finish()?.flush()?;
+    Ok(removed)
+}
+pub fn delete_members(tar_file: &str, patterns: &[&str]) -> Result<usize, io::Error> {
+    if is_encrypted_archive(tar_file)? {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "members cannot be deleted from an encrypted archive",
+        ));
+    }
+    let is_compressed = is_gzip(tar_file)?;
+    let orphaned_targets = find_orphaned_targets(tar_file, patterns)?;
+    let mut reader = open_archive(tar_file)?;
+
+    if is_compressed {
+        let temp_name = format!("{}.tmp", tar_file);
+        let removed = match write_compressed(&mut reader, &temp_name, patterns, &orphaned_targets) {
+            Ok(removed) => removed,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_name);
+                return Err(e);
+            }
+        };
+        fs::rename(&temp_name, tar_file)?;
+        Ok(removed)
+    } else {
+        // Members are only ever dropped or replaced by a link promoted to the same
+        // data, so the write position never overtakes the read position.
+        let output = OpenOptions::new().write(true).open(tar_file)?;
+        let mut writer = BufWriter::new(output);
+        let (removed, written) =
+            filter_members(&mut reader, &mut writer, patterns, &orphaned_targets)?;
+        let output = writer.into_inner().map_err(|e| e.into_error())?;
+        output.set_len(written)?;
+        Ok(removed)
+    }
+}
@@ -0,0 +1,153 @@
+use crate::pack_unpack::unpacking::{open_archive, read_block, UStarHeader};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+// Stored as an extended attribute record so that GNU tar and bsdtar accept it silently.
+pub const DIGEST_KEY: &str = "SCHILY.xattr.user.tararchiver.sha256";
+pub const MANIFEST_NAME: &str = "MANIFEST";
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+/// Passes data through unchanged while computing its SHA-256.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+    /// The digest of everything read so far.
+    pub fn hex_digest(&self) -> String {
+        to_hex(&self.hasher.clone().finalize())
+    }
+}
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.hasher.update(&buffer[..read]);
+        Ok(read)
+    }
+}
+pub fn pax_record(key: &str, value: &str) -> String {
+    let body_len = key.len() + value.len() + 3;
+    let mut total_len = body_len + body_len.to_string().len();
+    if total_len.to_string().len() != body_len.to_string().len() {
+        total_len += 1;
+    }
+    format!("{} {}={}\n", total_len, key, value)
+}
+pub fn parse_pax_records(data: &[u8]) -> HashMap<String, String> {
+    let mut records = HashMap::new();
+    let mut rest = data;
+    while let Some(space) = rest.iter().position(|&b| b == b' ') {
+        let length = match std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|length| length.parse::<usize>().ok())
+        {
+            Some(length) if length > space && length <= rest.len() => length,
+            _ => break,
+        };
+        let record = String::from_utf8_lossy(&rest[space + 1..length - 1]);
+        if let Some((key, value)) = record.split_once('=') {
+            records.insert(key.to_string(), value.to_string());
+        }
+        rest = &rest[length..];
+    }
+    records
+}
+pub fn manifest_line(digest: &str, file_name: &str) -> String {
+    format!("{}  {}\n", digest, file_name)
+}
+pub fn parse_manifest(data: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(digest, file_name)| (file_name.to_string(), digest.to_string()))
+        .collect()
+}
+pub fn report_manifest_mismatches(
+    manifest: &HashMap<String, String>,
+    computed: &HashMap<String, String>,
+) -> usize {
+    let mut mismatches = 0;
+    for (file_name, expected) in manifest {
+        match computed.get(file_name) {
+            Some(actual) if actual == expected => {}
+            Some(_) => {
+                println!("{}: digest mismatch against {}", file_name, MANIFEST_NAME);
+                mismatches += 1;
+            }
+            None => {
+                println!(
+                    "{}: listed in {} but not archived",
+                    file_name, MANIFEST_NAME
+                );
+                mismatches += 1;
+            }
+        }
+    }
+    mismatches
+}
+pub fn verify_archive(tar_file: &str) -> Result<usize, io::Error> {
+    let mut reader = open_archive(tar_file)?;
+    let mut buffer = vec![0; 512];
+    let mut pax_records: HashMap<String, String> = HashMap::new();
+    let mut computed: HashMap<String, String> = HashMap::new();
+    let mut manifest: Option<HashMap<String, String>> = None;
+    let mut mismatches = 0;
+
+    while read_block(&mut reader, &mut buffer)? {
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        let header = UStarHeader::from_bytes(&buffer);
+        let size = header.data_size();
+        let padding = (512 - (size % 512)) % 512;
+        let mut content = vec![0; size];
+        reader.read_exact(&mut content)?;
+        reader.read_exact(&mut vec![0; padding])?;
+
+        let file_name = pax_records
+            .get("path")
+            .cloned()
+            .unwrap_or_else(|| header.file_name());
+        match header.type_flag() {
+            'x' => {
+                pax_records = parse_pax_records(&content);
+                continue;
+            }
+            'g' => continue,
+            '0' | '\0' | '7' if file_name == MANIFEST_NAME => {
+                manifest = Some(parse_manifest(&content));
+            }
+            '0' | '\0' | '7' => {
+                let digest = sha256_hex(&content);
+                match pax_records.get(DIGEST_KEY) {
+                    Some(expected) if *expected == digest => println!("{}: OK", file_name),
+                    Some(_) => {
+                        println!("{}: digest mismatch", file_name);
+                        mismatches += 1;
+                    }
+                    None => println!("{}: {}", file_name, digest),
+                }
+                computed.insert(file_name, digest);
+            }
+            _ => {}
+        }
+        pax_records.clear();
+    }
+
+    if let Some(manifest) = manifest {
+        mismatches += report_manifest_mismatches(&manifest, &computed);
+    }
+    Ok(mismatches)
+}
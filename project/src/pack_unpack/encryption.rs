@@ -0,0 +1,234 @@
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::io::{self, BufRead, Read, Write};
+use std::num::NonZeroU32;
+
+pub const ENCRYPTION_MAGIC: &[u8; 8] = b"TARAENC1";
+pub const PASSPHRASE_VARIABLE: &str = "TAR_ARCHIVER_PASSPHRASE";
+const SALT_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = ENCRYPTION_MAGIC.len() + SALT_LEN + 8;
+const ITERATIONS: u32 = 600_000;
+// Headers are untrusted, so a forged iteration count must not stall decryption for hours.
+const MAX_ITERATIONS: u32 = 10 * ITERATIONS;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub fn is_encrypted(start: &[u8]) -> bool {
+    start.starts_with(ENCRYPTION_MAGIC)
+}
+pub fn read_passphrase() -> Result<String, io::Error> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VARIABLE) {
+        return Ok(passphrase);
+    }
+    println!("Passphrase: ");
+    let mut passphrase = String::new();
+    let stdin = io::stdin();
+    // Echo is only turned off when stdin is a terminal; piped passphrases are read as they are.
+    match tcgetattr(&stdin) {
+        Ok(original) => {
+            let mut silent = original.clone();
+            silent.local_flags.remove(LocalFlags::ECHO);
+            tcsetattr(&stdin, SetArg::TCSANOW, &silent)?;
+            let read = stdin.read_line(&mut passphrase);
+            tcsetattr(&stdin, SetArg::TCSANOW, &original)?;
+            println!();
+            read?;
+        }
+        Err(_) => {
+            stdin.read_line(&mut passphrase)?;
+        }
+    }
+    let passphrase = passphrase.trim_end_matches(['\r', '\n']).to_string();
+    if passphrase.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "passphrase must not be empty",
+        ));
+    }
+    Ok(passphrase)
+}
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<LessSafeKey, io::Error> {
+    let iterations = NonZeroU32::new(iterations)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid iteration count"))?;
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    let unbound_key = UnboundKey::new(&CHACHA20_POLY1305, &key)
+        .map_err(|_| io::Error::other("could not create encryption key"))?;
+    Ok(LessSafeKey::new(unbound_key))
+}
+fn chunk_nonce(counter: u64, last: bool) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..8].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    key: LessSafeKey,
+    header: Vec<u8>,
+    buffer: Vec<u8>,
+    counter: u64,
+}
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(mut inner: W, passphrase: &str) -> Result<Self, io::Error> {
+        let mut salt = [0u8; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| io::Error::other("could not generate a salt"))?;
+
+        let mut header = ENCRYPTION_MAGIC.to_vec();
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&ITERATIONS.to_be_bytes());
+        header.extend_from_slice(&(CHUNK_SIZE as u32).to_be_bytes());
+        inner.write_all(&header)?;
+
+        Ok(EncryptingWriter {
+            inner,
+            key: derive_key(passphrase, &salt, ITERATIONS)?,
+            header,
+            buffer: Vec::with_capacity(CHUNK_SIZE * 2),
+            counter: 0,
+        })
+    }
+    fn seal_chunk(&mut self, mut chunk: Vec<u8>, last: bool) -> Result<(), io::Error> {
+        self.key
+            .seal_in_place_append_tag(
+                chunk_nonce(self.counter, last),
+                Aad::from(&self.header),
+                &mut chunk,
+            )
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.counter += 1;
+        self.inner.write_all(&chunk)
+    }
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        let rest = std::mem::take(&mut self.buffer);
+        self.seal_chunk(rest, true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        // A full chunk is only sealed once more data follows it, so the final
+        // chunk can always be marked as last in `finish`.
+        while self.buffer.len() > CHUNK_SIZE {
+            let rest = self.buffer.split_off(CHUNK_SIZE);
+            let chunk = std::mem::replace(&mut self.buffer, rest);
+            self.seal_chunk(chunk, false)?;
+        }
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+pub struct DecryptingReader<R: BufRead> {
+    inner: R,
+    key: LessSafeKey,
+    header: Vec<u8>,
+    chunk_size: usize,
+    plaintext: Vec<u8>,
+    position: usize,
+    counter: u64,
+    finished: bool,
+}
+impl<R: BufRead> DecryptingReader<R> {
+    pub fn new(mut inner: R, passphrase: &str) -> Result<Self, io::Error> {
+        let mut header = vec![0; HEADER_LEN];
+        inner.read_exact(&mut header)?;
+        if !is_encrypted(&header) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an encrypted archive",
+            ));
+        }
+        let salt = &header[8..8 + SALT_LEN];
+        let iterations = u32::from_be_bytes(header[24..28].try_into().unwrap());
+        let chunk_size = u32::from_be_bytes(header[28..32].try_into().unwrap()) as usize;
+        if iterations > MAX_ITERATIONS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("iteration count {} is too large", iterations),
+            ));
+        }
+        if chunk_size != CHUNK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported chunk size {}", chunk_size),
+            ));
+        }
+
+        Ok(DecryptingReader {
+            key: derive_key(passphrase, salt, iterations)?,
+            inner,
+            header,
+            chunk_size,
+            plaintext: Vec::new(),
+            position: 0,
+            counter: 0,
+            finished: false,
+        })
+    }
+    fn open_next_chunk(&mut self) -> Result<(), io::Error> {
+        let mut chunk = Vec::with_capacity(self.chunk_size + TAG_LEN);
+        (&mut self.inner)
+            .take((self.chunk_size + TAG_LEN) as u64)
+            .read_to_end(&mut chunk)?;
+        let last = self.inner.fill_buf()?.is_empty();
+        if chunk.len() < TAG_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "encrypted archive is truncated",
+            ));
+        }
+
+        let plaintext_len = self
+            .key
+            .open_in_place(
+                chunk_nonce(self.counter, last),
+                Aad::from(&self.header),
+                &mut chunk,
+            )
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "chunk {} failed authentication: wrong passphrase, or the archive was modified or truncated",
+                        self.counter
+                    ),
+                )
+            })?
+            .len();
+        chunk.truncate(plaintext_len);
+
+        self.plaintext = chunk;
+        self.position = 0;
+        self.counter += 1;
+        self.finished = last;
+        Ok(())
+    }
+}
+impl<R: BufRead> Read for DecryptingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.open_next_chunk()?;
+        }
+        let count = buffer.len().min(self.plaintext.len() - self.position);
+        buffer[..count].copy_from_slice(&self.plaintext[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
@@ -0,0 +1,248 @@
+use crate::pack_unpack::digests::parse_pax_records;
+use crate::pack_unpack::unpacking::{read_block, UStarHeader};
+use std::io::{self, Read};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryKind {
+    File,
+    HardLink,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    Fifo,
+}
+impl EntryKind {
+    pub fn type_flag(self) -> u8 {
+        match self {
+            EntryKind::File => b'0',
+            EntryKind::HardLink => b'1',
+            EntryKind::Symlink => b'2',
+            EntryKind::CharDevice => b'3',
+            EntryKind::BlockDevice => b'4',
+            EntryKind::Directory => b'5',
+            EntryKind::Fifo => b'6',
+        }
+    }
+    pub fn file_type_bits(self) -> u32 {
+        match self {
+            EntryKind::File | EntryKind::HardLink => 0o100000,
+            EntryKind::Symlink => 0o120000,
+            EntryKind::CharDevice => 0o020000,
+            EntryKind::BlockDevice => 0o060000,
+            EntryKind::Directory => 0o040000,
+            EntryKind::Fifo => 0o010000,
+        }
+    }
+    pub fn from_file_type_bits(mode: u32) -> Option<Self> {
+        match mode & 0o170000 {
+            0o100000 => Some(EntryKind::File),
+            0o120000 => Some(EntryKind::Symlink),
+            0o020000 => Some(EntryKind::CharDevice),
+            0o060000 => Some(EntryKind::BlockDevice),
+            0o040000 => Some(EntryKind::Directory),
+            0o010000 => Some(EntryKind::Fifo),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub user_name: String,
+    pub group_name: String,
+    pub mtime: i64,
+    pub link_name: String,
+    pub device: (u64, u64),
+    pub data: Vec<u8>,
+}
+impl Entry {
+    pub fn new(name: &str, kind: EntryKind) -> Self {
+        Entry {
+            name: name.trim_end_matches('/').to_string(),
+            kind,
+            mode: if kind == EntryKind::Directory {
+                0o755
+            } else {
+                0o644
+            },
+            uid: 0,
+            gid: 0,
+            user_name: String::new(),
+            group_name: String::new(),
+            mtime: 0,
+            link_name: String::new(),
+            device: (0, 0),
+            data: Vec::new(),
+        }
+    }
+    pub fn from_header(header: &UStarHeader, name: String, data: Vec<u8>) -> Option<Self> {
+        let kind = match header.type_flag() {
+            '0' | '\0' | '7' => EntryKind::File,
+            '1' => EntryKind::HardLink,
+            '2' => EntryKind::Symlink,
+            '3' => EntryKind::CharDevice,
+            '4' => EntryKind::BlockDevice,
+            '5' | 'D' => EntryKind::Directory,
+            '6' => EntryKind::Fifo,
+            _ => return None,
+        };
+        Some(Entry {
+            name: name.trim_end_matches('/').to_string(),
+            kind,
+            mode: header.mode_bits() & 0o7777,
+            uid: header.uid(),
+            gid: header.gid(),
+            user_name: header.user_name(),
+            group_name: header.group_name(),
+            mtime: header.modification_time(),
+            link_name: header.link_name(),
+            device: header.device_numbers(),
+            data: if kind == EntryKind::File {
+                data
+            } else {
+                Vec::new()
+            },
+        })
+    }
+    pub fn to_header(&self) -> Result<UStarHeader, io::Error> {
+        let mut block = vec![0u8; 512];
+        let name = if self.kind == EntryKind::Directory {
+            format!("{}/", self.name)
+        } else {
+            self.name.clone()
+        };
+        let (prefix, name) = split_name(&name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("name '{}' is too long for a ustar header", self.name),
+            )
+        })?;
+        if self.link_name.len() > 100 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "link target of '{}' is too long for a ustar header",
+                    self.name
+                ),
+            ));
+        }
+
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        set_octal(&mut block[100..108], self.mode as u64, &self.name, "mode")?;
+        set_octal(&mut block[108..116], self.uid as u64, &self.name, "uid")?;
+        set_octal(&mut block[116..124], self.gid as u64, &self.name, "gid")?;
+        set_octal(
+            &mut block[124..136],
+            self.data.len() as u64,
+            &self.name,
+            "size",
+        )?;
+        set_octal(
+            &mut block[136..148],
+            self.mtime.max(0) as u64,
+            &self.name,
+            "mtime",
+        )?;
+        block[156] = self.kind.type_flag();
+        block[157..157 + self.link_name.len()].copy_from_slice(self.link_name.as_bytes());
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        copy_truncated(&mut block[265..297], &self.user_name);
+        copy_truncated(&mut block[297..329], &self.group_name);
+        if matches!(self.kind, EntryKind::CharDevice | EntryKind::BlockDevice) {
+            set_octal(&mut block[329..337], self.device.0, &self.name, "major")?;
+            set_octal(&mut block[337..345], self.device.1, &self.name, "minor")?;
+        }
+        block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        let mut header = UStarHeader::from_bytes(&block);
+        header.update_checksum();
+        Ok(header)
+    }
+}
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.char_indices()
+        .filter(|&(_, c)| c == '/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && rest.len() <= 100 && !rest.is_empty())
+}
+fn set_octal(field: &mut [u8], value: u64, name: &str, what: &str) -> Result<(), io::Error> {
+    let text = format!("{:0>width$o}\0", value, width = field.len() - 1);
+    if text.len() > field.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} of '{}' ({}) is too large for a ustar header",
+                what, name, value
+            ),
+        ));
+    }
+    field.copy_from_slice(text.as_bytes());
+    Ok(())
+}
+fn copy_truncated(field: &mut [u8], text: &str) {
+    let len = text.len().min(field.len());
+    field[..len].copy_from_slice(&text.as_bytes()[..len]);
+}
+pub fn read_tar_entries(reader: &mut dyn Read) -> Result<Vec<Entry>, io::Error> {
+    let mut buffer = vec![0; 512];
+    let mut entries = Vec::new();
+    let mut pax_path: Option<String> = None;
+
+    while read_block(reader, &mut buffer)? {
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        let header = UStarHeader::from_bytes(&buffer);
+        let size = header.data_size();
+        let mut data = Vec::with_capacity(size);
+        (&mut *reader).take(size as u64).read_to_end(&mut data)?;
+        if data.len() < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "archive is truncated inside member '{}'",
+                    header.file_name()
+                ),
+            ));
+        }
+        io::copy(
+            &mut (&mut *reader).take(((512 - (size % 512)) % 512) as u64),
+            &mut io::sink(),
+        )?;
+
+        match header.type_flag() {
+            'x' => pax_path = parse_pax_records(&data).remove("path"),
+            'g' => {}
+            type_flag => {
+                let name = pax_path.take().unwrap_or_else(|| header.file_name());
+                match Entry::from_header(&header, name.clone(), data) {
+                    Some(entry) => entries.push(entry),
+                    None => println!(
+                        "Warning: {}: type '{}' is not supported, skipped",
+                        name, type_flag
+                    ),
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+pub fn write_tar_entries(entries: &[Entry]) -> Result<Vec<u8>, io::Error> {
+    let mut tar_buffer = Vec::new();
+    for entry in entries {
+        tar_buffer.extend_from_slice(&entry.to_header()?.as_bytes());
+        tar_buffer.extend_from_slice(&entry.data);
+        tar_buffer.resize(tar_buffer.len().div_ceil(512) * 512, 0);
+    }
+    tar_buffer.extend_from_slice(&[0; 1024]);
+    Ok(tar_buffer)
+}
@@ -0,0 +1,291 @@
+use crate::pack_unpack::digests::{sha256_hex, HashingReader};
+use crate::pack_unpack::indexing::{build_index, IndexEntry};
+use crate::pack_unpack::spooling::open_spool;
+use crate::pack_unpack::unpacking::{
+    decompress, extract_stream, open_archive, UStarHeader, UnpackOptions,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+const INDEX_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+
+struct ImageLayer {
+    name: String,
+    diff_id: String,
+}
+/// Where the image files come from: an OCI layout directory, or the members of a
+/// `docker save` tarball (which newer Docker versions also lay out as OCI). A tarball is
+/// only indexed; members are read from it when needed.
+enum ImageSource {
+    Directory(PathBuf),
+    Archive {
+        path: String,
+        members: HashMap<String, IndexEntry>,
+        seekable: bool,
+    },
+}
+impl ImageSource {
+    fn open(image: &str) -> Result<Self, io::Error> {
+        if Path::new(image).is_dir() {
+            return Ok(ImageSource::Directory(PathBuf::from(image)));
+        }
+        let members = build_index(&mut open_archive(image)?)?
+            .into_iter()
+            .filter(|entry| matches!(entry.type_flag, '0' | '\0' | '7'))
+            .map(|entry| (entry.name.trim_start_matches("./").to_string(), entry))
+            .collect();
+        // A plain tarball can be read at any offset; anything else is decoded from the start.
+        let mut block = [0; 512];
+        let seekable = File::open(image)?.read_exact(&mut block).is_ok()
+            && UStarHeader::from_bytes(&block).checksum_is_valid();
+        Ok(ImageSource::Archive {
+            path: image.to_string(),
+            members,
+            seekable,
+        })
+    }
+    fn contains(&self, name: &str) -> bool {
+        match self {
+            ImageSource::Directory(directory) => directory.join(name).is_file(),
+            ImageSource::Archive { members, .. } => members.contains_key(name),
+        }
+    }
+    fn open_file(&self, name: &str) -> Result<Box<dyn Read>, io::Error> {
+        match self {
+            ImageSource::Directory(directory) => {
+                Ok(Box::new(BufReader::new(File::open(directory.join(name))?)))
+            }
+            ImageSource::Archive {
+                path,
+                members,
+                seekable,
+            } => {
+                let entry = members.get(name).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is missing from the image", name),
+                    )
+                })?;
+                if *seekable {
+                    let mut file = File::open(path)?;
+                    file.seek(SeekFrom::Start(entry.data_offset))?;
+                    return Ok(Box::new(BufReader::new(file).take(entry.size)));
+                }
+                let mut reader = open_archive(path)?;
+                io::copy(&mut (&mut reader).take(entry.data_offset), &mut io::sink())?;
+                Ok(Box::new(reader.take(entry.size)))
+            }
+        }
+    }
+    fn read(&self, name: &str) -> Result<Vec<u8>, io::Error> {
+        let mut data = Vec::new();
+        self.open_file(name)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+    fn read_json(&self, name: &str) -> Result<Value, io::Error> {
+        parse_json(&self.read(name)?, name)
+    }
+    fn read_blob(&self, descriptor: &Value) -> Result<Vec<u8>, io::Error> {
+        let digest = descriptor["digest"].as_str().unwrap_or_default();
+        let hex = sha256_digest(digest)?;
+        let blob = self.read(&format!("blobs/sha256/{}", hex))?;
+        if sha256_hex(&blob) != hex {
+            return Err(invalid_image(format!(
+                "blob {} does not match its digest",
+                digest
+            )));
+        }
+        if descriptor["size"]
+            .as_u64()
+            .is_some_and(|size| size != blob.len() as u64)
+        {
+            return Err(invalid_image(format!(
+                "blob {} does not have the recorded size",
+                digest
+            )));
+        }
+        Ok(blob)
+    }
+    /// Checks a blob against its descriptor without holding it, and returns its name.
+    fn verify_blob(&self, descriptor: &Value) -> Result<String, io::Error> {
+        let digest = descriptor["digest"].as_str().unwrap_or_default();
+        let name = format!("blobs/sha256/{}", sha256_digest(digest)?);
+        let mut blob = HashingReader::new(self.open_file(&name)?);
+        let size = io::copy(&mut blob, &mut io::sink())?;
+        if blob.hex_digest() != sha256_digest(digest)? {
+            return Err(invalid_image(format!(
+                "blob {} does not match its digest",
+                digest
+            )));
+        }
+        if descriptor["size"]
+            .as_u64()
+            .is_some_and(|expected| expected != size)
+        {
+            return Err(invalid_image(format!(
+                "blob {} does not have the recorded size",
+                digest
+            )));
+        }
+        Ok(name)
+    }
+}
+fn invalid_image(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+fn parse_json(data: &[u8], name: &str) -> Result<Value, io::Error> {
+    serde_json::from_slice(data).map_err(|e| invalid_image(format!("{}: {}", name, e)))
+}
+fn sha256_digest(digest: &str) -> Result<&str, io::Error> {
+    digest
+        .strip_prefix("sha256:")
+        .filter(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(|| invalid_image(format!("unsupported digest '{}'", digest)))
+}
+/// The diffID of the layer at `position`; every layer has to be checked against one.
+fn layer_diff_id(diff_ids: &[Value], position: usize) -> Result<String, io::Error> {
+    diff_ids
+        .get(position)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| {
+            invalid_image(format!(
+                "the config has no diffID for layer {}",
+                position + 1
+            ))
+        })
+}
+/// Names in a legacy manifest.json have to stay inside the tarball or directory.
+fn member_name(value: &Value) -> Result<&str, io::Error> {
+    let name = value.as_str().unwrap_or_default();
+    let path = Path::new(name);
+    if name.is_empty()
+        || path.is_absolute()
+        || path
+            .components()
+            .any(|component| component == Component::ParentDir)
+    {
+        return Err(invalid_image(format!(
+            "manifest.json names '{}', which is not a relative path",
+            name
+        )));
+    }
+    Ok(name)
+}
+fn platform_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        architecture => architecture,
+    }
+}
+/// Follows image indexes down to a single manifest, preferring the one for this machine.
+fn select_manifest(source: &ImageSource, index: &Value) -> Result<Value, io::Error> {
+    let manifests = index["manifests"].as_array().cloned().unwrap_or_default();
+    let descriptor = manifests
+        .iter()
+        .find(|descriptor| {
+            descriptor["platform"]["os"] == "linux"
+                && descriptor["platform"]["architecture"] == platform_architecture()
+        })
+        .or_else(|| manifests.first())
+        .ok_or_else(|| invalid_image("the image index lists no manifests".to_string()))?;
+
+    let manifest = parse_json(&source.read_blob(descriptor)?, "manifest")?;
+    let media_type = manifest["mediaType"]
+        .as_str()
+        .or(descriptor["mediaType"].as_str())
+        .unwrap_or_default();
+    if INDEX_MEDIA_TYPES.contains(&media_type) || manifest["manifests"].is_array() {
+        select_manifest(source, &manifest)
+    } else {
+        Ok(manifest)
+    }
+}
+/// The layer files in order, with the diffID each one should have once decompressed.
+fn image_layers(source: &ImageSource) -> Result<Vec<ImageLayer>, io::Error> {
+    if source.contains("index.json") {
+        let manifest = select_manifest(source, &source.read_json("index.json")?)?;
+        let config = parse_json(&source.read_blob(&manifest["config"])?, "config")?;
+        let diff_ids = config["rootfs"]["diff_ids"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let mut layers = Vec::new();
+        for (position, descriptor) in manifest["layers"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+        {
+            layers.push(ImageLayer {
+                name: source.verify_blob(descriptor)?,
+                diff_id: layer_diff_id(&diff_ids, position)?,
+            });
+        }
+        Ok(layers)
+    } else if source.contains("manifest.json") {
+        // Legacy docker save layout: <id>/layer.tar members listed in manifest.json.
+        let manifest = source.read_json("manifest.json")?;
+        let image = &manifest[0];
+        let config = source.read_json(member_name(&image["Config"])?)?;
+        let diff_ids = config["rootfs"]["diff_ids"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let mut layers = Vec::new();
+        for (position, layer) in image["Layers"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+        {
+            layers.push(ImageLayer {
+                name: member_name(layer)?.to_string(),
+                diff_id: layer_diff_id(&diff_ids, position)?,
+            });
+        }
+        Ok(layers)
+    } else {
+        Err(invalid_image(
+            "neither index.json nor manifest.json found, this is not an OCI layout or docker save tarball"
+                .to_string(),
+        ))
+    }
+}
+pub fn apply_image(image: &str, rootfs: &str) -> Result<usize, io::Error> {
+    let source = ImageSource::open(image)?;
+    let layers = image_layers(&source)?;
+    fs::create_dir_all(rootfs)?;
+
+    let options = UnpackOptions {
+        destination: Some(rootfs.to_string()),
+        layer: true,
+        ..UnpackOptions::default()
+    };
+    for (position, image_layer) in layers.iter().enumerate() {
+        // The layer is staged in a temporary file and only applied once its diffID matches.
+        let mut staged = open_spool()?;
+        let mut layer = HashingReader::new(decompress(source.open_file(&image_layer.name)?)?);
+        io::copy(&mut layer, &mut staged)?;
+        if layer.hex_digest() != sha256_digest(&image_layer.diff_id)? {
+            return Err(invalid_image(format!(
+                "layer {} does not match its diffID {}",
+                position + 1,
+                image_layer.diff_id
+            )));
+        }
+        staged.seek(SeekFrom::Start(0))?;
+        extract_stream(&mut BufReader::new(staged), &options)?;
+    }
+    Ok(layers.len())
+}
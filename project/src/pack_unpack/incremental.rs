@@ -0,0 +1,146 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const SNAPSHOT_HEADER: &str = "tararchiver-snapshot 1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotRecord {
+    pub is_dir: bool,
+    pub device: u64,
+    pub inode: u64,
+    pub mtime: i64,
+    pub ctime: i64,
+}
+impl SnapshotRecord {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        SnapshotRecord {
+            is_dir: metadata.is_dir(),
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            mtime: metadata.mtime(),
+            ctime: metadata.ctime(),
+        }
+    }
+}
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub records: HashMap<String, SnapshotRecord>,
+}
+impl Snapshot {
+    pub fn load(snapshot_file: &str) -> Result<Self, io::Error> {
+        let content = match fs::read_to_string(snapshot_file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Snapshot::default()),
+            Err(e) => return Err(e),
+        };
+        let mut lines = content.lines();
+        if lines.next() != Some(SNAPSHOT_HEADER) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a snapshot file", snapshot_file),
+            ));
+        }
+
+        let mut records = HashMap::new();
+        for line in lines {
+            let parts: Vec<&str> = line.splitn(6, ' ').collect();
+            if parts.len() < 6 {
+                continue;
+            }
+            let record = SnapshotRecord {
+                is_dir: parts[0] == "D",
+                device: parts[1].parse().unwrap_or(0),
+                inode: parts[2].parse().unwrap_or(0),
+                mtime: parts[3].parse().unwrap_or(0),
+                ctime: parts[4].parse().unwrap_or(0),
+            };
+            records.insert(parts[5].to_string(), record);
+        }
+        Ok(Snapshot { records })
+    }
+    pub fn save(&self, snapshot_file: &str) -> Result<(), io::Error> {
+        let mut names: Vec<&String> = self.records.keys().collect();
+        names.sort();
+
+        let mut content = format!("{}\n", SNAPSHOT_HEADER);
+        for name in names {
+            let record = &self.records[name];
+            content.push_str(&format!(
+                "{} {} {} {} {} {}\n",
+                if record.is_dir { "D" } else { "F" },
+                record.device,
+                record.inode,
+                record.mtime,
+                record.ctime,
+                name
+            ));
+        }
+        let temp_file = format!("{}.tmp", snapshot_file);
+        fs::write(&temp_file, content)?;
+        fs::rename(temp_file, snapshot_file)
+    }
+    pub fn is_unchanged(&self, path_name: &str, metadata: &Metadata) -> bool {
+        self.records.get(path_name) == Some(&SnapshotRecord::from_metadata(metadata))
+    }
+}
+#[derive(Debug, Default)]
+pub struct IncrementalState {
+    pub previous: Snapshot,
+    pub current: Snapshot,
+}
+pub fn dumpdir_entry(control: char, name: &str) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(name.len() + 2);
+    entry.push(control as u8);
+    entry.extend_from_slice(name.as_bytes());
+    entry.push(0);
+    entry
+}
+pub fn parse_dumpdir(data: &[u8]) -> Vec<(char, String)> {
+    data.split(|&b| b == 0)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            (
+                entry[0] as char,
+                String::from_utf8_lossy(&entry[1..]).to_string(),
+            )
+        })
+        .collect()
+}
+/// Removes whatever in `directory` the dumpdir does not list. The directory must be a real
+/// directory under `root`, so a symlink extracted earlier cannot aim the removal elsewhere.
+pub fn apply_dumpdir(root: &Path, directory: &Path, data: &[u8]) -> Result<(), io::Error> {
+    let inside_root = fs::symlink_metadata(directory)?.is_dir()
+        && directory.canonicalize()?.starts_with(root.canonicalize()?);
+    if !inside_root {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "refusing to prune {}: it is not a directory inside {}",
+                directory.display(),
+                root.display()
+            ),
+        ));
+    }
+    let kept: HashSet<String> = parse_dumpdir(data)
+        .into_iter()
+        .map(|(_, name)| name)
+        .collect();
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if kept.contains(&name) {
+            continue;
+        }
+        println!("Removing {}", entry.path().display());
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
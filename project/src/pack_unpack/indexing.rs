@@ -0,0 +1,233 @@
+use crate::pack_unpack::ar::{is_ar, read_ar};
+use crate::pack_unpack::cpio::{is_cpio, read_cpio_entries};
+use crate::pack_unpack::digests::parse_pax_records;
+use crate::pack_unpack::unpacking::{
+    is_encrypted_archive, is_gzip, open_archive, read_block, UStarHeader,
+};
+use crate::pack_unpack::volumes::is_first_volume;
+use crate::pack_unpack::zip::{is_zip, read_central_directory};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+
+const INDEX_HEADER: &str = "tararchiver-index 2";
+const FRAME_SIZE: u64 = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub header_offset: u64,
+    pub data_offset: u64,
+    pub size: u64,
+    pub type_flag: char,
+    pub frame_offset: u64,
+    pub frame_start: u64,
+    pub name: String,
+}
+pub fn index_path(archive_file: &str) -> String {
+    format!("{}.idx", archive_file)
+}
+pub fn build_index(reader: &mut dyn Read) -> Result<Vec<IndexEntry>, io::Error> {
+    let mut buffer = vec![0; 512];
+    let mut entries = Vec::new();
+    let mut offset: u64 = 0;
+    let mut member_start: Option<u64> = None;
+    let mut pax_path: Option<String> = None;
+
+    while read_block(reader, &mut buffer)? {
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        let header = UStarHeader::from_bytes(&buffer);
+        let size = header.data_size();
+        let padding = (512 - (size % 512)) % 512;
+        let header_offset = *member_start.get_or_insert(offset);
+        offset += 512;
+
+        let mut content = Vec::new();
+        if header.type_flag() == 'x' {
+            (&mut *reader).take(size as u64).read_to_end(&mut content)?;
+        } else {
+            io::copy(&mut (&mut *reader).take(size as u64), &mut io::sink())?;
+        }
+        io::copy(&mut (&mut *reader).take(padding as u64), &mut io::sink())?;
+
+        match header.type_flag() {
+            'x' => pax_path = parse_pax_records(&content).remove("path"),
+            'g' => {}
+            type_flag => {
+                entries.push(IndexEntry {
+                    header_offset,
+                    data_offset: offset,
+                    size: size as u64,
+                    type_flag,
+                    frame_offset: header_offset,
+                    frame_start: header_offset,
+                    name: pax_path.take().unwrap_or_else(|| header.file_name()),
+                });
+                member_start = None;
+            }
+        }
+        offset += (size + padding) as u64;
+    }
+    Ok(entries)
+}
+fn archive_stamp(archive_file: &str) -> Result<String, io::Error> {
+    let metadata = fs::metadata(archive_file)?;
+    Ok(format!("{} {}", metadata.len(), metadata.mtime()))
+}
+pub fn write_index(archive_file: &str, entries: &[IndexEntry]) -> Result<String, io::Error> {
+    let mut content = format!("{}\n{}\n", INDEX_HEADER, archive_stamp(archive_file)?);
+    for entry in entries {
+        content.push_str(&format!(
+            "{} {} {} {} {} {} {}\n",
+            entry.header_offset,
+            entry.data_offset,
+            entry.size,
+            if entry.type_flag == '\0' {
+                '0'
+            } else {
+                entry.type_flag
+            },
+            entry.frame_offset,
+            entry.frame_start,
+            entry.name
+        ));
+    }
+    let index_file = index_path(archive_file);
+    fs::write(&index_file, content)?;
+    Ok(index_file)
+}
+pub fn read_index(archive_file: &str) -> Result<Option<Vec<IndexEntry>>, io::Error> {
+    let content = match fs::read_to_string(index_path(archive_file)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut lines = content.lines();
+    if lines.next() != Some(INDEX_HEADER) {
+        return Ok(None);
+    }
+    if lines.next() != Some(archive_stamp(archive_file)?.as_str()) {
+        println!(
+            "Ignoring {}: the archive changed after it was indexed",
+            index_path(archive_file)
+        );
+        return Ok(None);
+    }
+
+    let mut entries = Vec::new();
+    for line in lines {
+        let parts: Vec<&str> = line.splitn(7, ' ').collect();
+        if parts.len() < 7 {
+            continue;
+        }
+        entries.push(IndexEntry {
+            header_offset: parts[0].parse().unwrap_or(0),
+            data_offset: parts[1].parse().unwrap_or(0),
+            size: parts[2].parse().unwrap_or(0),
+            type_flag: parts[3].chars().next().unwrap_or('0'),
+            frame_offset: parts[4].parse().unwrap_or(0),
+            frame_start: parts[5].parse().unwrap_or(0),
+            name: parts[6].to_string(),
+        });
+    }
+    Ok(Some(entries))
+}
+pub fn write_seekable_gzip(
+    tar_buffer: &[u8],
+    archive_file: &str,
+    entries: &mut [IndexEntry],
+) -> Result<(), io::Error> {
+    let mut output = BufWriter::new(File::create(archive_file)?);
+    let mut frame_start: u64 = 0;
+    let mut frame_offset: u64 = 0;
+    let mut first_entry = 0;
+
+    for boundary in 0..=entries.len() {
+        let end = match entries.get(boundary) {
+            Some(entry) if entry.header_offset - frame_start < FRAME_SIZE => continue,
+            Some(entry) => entry.header_offset,
+            None => tar_buffer.len() as u64,
+        };
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar_buffer[frame_start as usize..end as usize])?;
+        let frame = encoder.finish()?;
+        output.write_all(&frame)?;
+
+        for entry in &mut entries[first_entry..boundary] {
+            entry.frame_offset = frame_offset;
+            entry.frame_start = frame_start;
+        }
+        first_entry = boundary;
+        frame_start = end;
+        frame_offset += frame.len() as u64;
+    }
+    output.flush()
+}
+pub fn is_seekable_archive(archive_file: &str) -> Result<bool, io::Error> {
+    Ok(!is_first_volume(archive_file) && !is_encrypted_archive(archive_file)?)
+}
+pub fn open_member(archive_file: &str, entry: &IndexEntry) -> Result<Box<dyn Read>, io::Error> {
+    let is_compressed = is_gzip(archive_file)?;
+    let mut file = File::open(archive_file)?;
+    file.seek(SeekFrom::Start(entry.frame_offset))?;
+
+    let mut reader: Box<dyn Read> = if is_compressed {
+        Box::new(MultiGzDecoder::new(BufReader::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    io::copy(
+        &mut (&mut reader).take(entry.header_offset - entry.frame_start),
+        &mut io::sink(),
+    )?;
+    Ok(reader)
+}
+pub fn index_archive(archive_file: &str) -> Result<usize, io::Error> {
+    if !is_seekable_archive(archive_file)? || is_gzip(archive_file)? {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "only uncompressed, unencrypted single-volume archives can be indexed afterwards; \
+             pack compressed archives with --seekable instead",
+        ));
+    }
+    let entries = build_index(&mut open_archive(archive_file)?)?;
+    write_index(archive_file, &entries)?;
+    Ok(entries.len())
+}
+pub fn list_members(archive_file: &str) -> Result<usize, io::Error> {
+    if is_zip(archive_file)? {
+        let members = read_central_directory(&mut BufReader::new(File::open(archive_file)?))?;
+        for member in &members {
+            println!("{}", member.name);
+        }
+        return Ok(members.len());
+    }
+    let mut reader = BufReader::new(open_archive(archive_file)?);
+    if is_ar(reader.fill_buf()?) {
+        let members = read_ar(&mut reader)?;
+        for member in &members {
+            println!("{}", member.name);
+        }
+        return Ok(members.len());
+    }
+    if is_cpio(reader.fill_buf()?) {
+        let entries = read_cpio_entries(&mut reader)?;
+        for entry in &entries {
+            println!("{}", entry.name);
+        }
+        return Ok(entries.len());
+    }
+    let entries = match read_index(archive_file)? {
+        Some(entries) => entries,
+        None => build_index(&mut reader)?,
+    };
+    for entry in &entries {
+        println!("{}", entry.name);
+    }
+    Ok(entries.len())
+}
@@ -0,0 +1,239 @@
+use crate::pack_unpack::digests::sha256_hex;
+use crate::pack_unpack::entry::{write_tar_entries, Entry, EntryKind};
+use crate::pack_unpack::packing::read_tree_entries;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+pub const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+#[derive(Debug, PartialEq)]
+pub enum Whiteout {
+    Remove(String),
+    Opaque(String),
+}
+fn split_parent(name: &str) -> (&str, &str) {
+    match name.rsplit_once('/') {
+        Some((parent, base)) => (parent, base),
+        None => ("", name),
+    }
+}
+fn join_name(parent: &str, base: &str) -> String {
+    if parent.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}/{}", parent, base)
+    }
+}
+fn invalid_layer_path(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("refusing layer path '{}' outside the root", name),
+    )
+}
+pub fn parse_whiteout(file_name: &str) -> Result<Option<Whiteout>, io::Error> {
+    let (parent, base) = split_parent(file_name.trim_end_matches('/'));
+    if base == OPAQUE_WHITEOUT {
+        return Ok(Some(Whiteout::Opaque(parent.to_string())));
+    }
+    match base.strip_prefix(WHITEOUT_PREFIX) {
+        // These would hide the parent directory itself, or the one above it.
+        Some("" | "." | "..") => Err(invalid_layer_path(file_name)),
+        Some(hidden) => Ok(Some(Whiteout::Remove(join_name(parent, hidden)))),
+        None => Ok(None),
+    }
+}
+/// Layers only ever change the tree they are applied to, so no path may climb out of it.
+fn check_layer_path(path: &Path) -> Result<(), io::Error> {
+    if path
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        return Err(invalid_layer_path(&path.to_string_lossy()));
+    }
+    Ok(())
+}
+pub fn remove_path(path: &Path) -> Result<(), io::Error> {
+    check_layer_path(path)?;
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+/// Makes room for a layer entry: whatever a lower layer left at the path is replaced,
+/// except a directory that stays a directory, which is merged.
+pub fn prepare_layer_path(path: &Path, is_directory: bool) -> Result<(), io::Error> {
+    check_layer_path(path)?;
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() && is_directory => Ok(()),
+        Ok(_) => remove_path(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+/// Empties a directory marked opaque, keeping what the current layer already put there.
+/// A symlink at its place is removed rather than followed, and the directory must resolve
+/// to somewhere under `root`.
+pub fn clear_directory(
+    root: &Path,
+    directory: &Path,
+    keep: &HashSet<PathBuf>,
+) -> Result<(), io::Error> {
+    check_layer_path(directory)?;
+    match fs::symlink_metadata(directory) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(metadata) if metadata.is_symlink() => return fs::remove_file(directory),
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    if !directory.canonicalize()?.starts_with(root.canonicalize()?) {
+        return Err(invalid_layer_path(&directory.to_string_lossy()));
+    }
+    for child in fs::read_dir(directory)? {
+        let child = child?.path();
+        if !keep.contains(&child) {
+            remove_path(&child)?;
+        }
+    }
+    Ok(())
+}
+fn tree_entries(directory: &str) -> Result<Vec<Entry>, io::Error> {
+    let mut entries = read_tree_entries(directory)?;
+    if entries.is_empty() {
+        return Ok(entries);
+    }
+    // Layer members are relative to the root of the image, without the tree's own name.
+    let top = entries.remove(0).name;
+    for entry in &mut entries {
+        if let Some(rest) = entry.name.strip_prefix(&top) {
+            entry.name = rest.trim_start_matches('/').to_string();
+        }
+        if entry.kind == EntryKind::HardLink {
+            if let Some(rest) = entry.link_name.strip_prefix(&top) {
+                entry.link_name = rest.trim_start_matches('/').to_string();
+            }
+        }
+    }
+    Ok(entries)
+}
+fn entry_changed(old: &Entry, new: &Entry) -> bool {
+    old.kind != new.kind
+        || old.mode != new.mode
+        || old.uid != new.uid
+        || old.gid != new.gid
+        || old.mtime != new.mtime
+        || old.link_name != new.link_name
+        || old.device != new.device
+        || old.data != new.data
+}
+fn include_with_parents(name: &str, included: &mut HashSet<String>) {
+    let mut name = name;
+    while included.insert(name.to_string()) {
+        match name.rsplit_once('/') {
+            Some((parent, _)) => name = parent,
+            None => break,
+        }
+    }
+}
+pub fn diff_layer(
+    old_dir: &str,
+    new_dir: &str,
+    layer_name: &str,
+    compress: bool,
+) -> Result<(String, String), io::Error> {
+    let old_entries: HashMap<String, Entry> = tree_entries(old_dir)?
+        .into_iter()
+        .map(|entry| (entry.name.clone(), entry))
+        .collect();
+    let new_entries = tree_entries(new_dir)?;
+    let new_names: HashSet<&str> = new_entries
+        .iter()
+        .map(|entry| entry.name.as_str())
+        .collect();
+
+    let mut included = HashSet::new();
+    for entry in &new_entries {
+        if old_entries
+            .get(&entry.name)
+            .is_none_or(|old| entry_changed(old, entry))
+        {
+            include_with_parents(&entry.name, &mut included);
+        }
+    }
+    // A rewritten file gets a new inode, links to it have to be rewritten along with it.
+    for entry in &new_entries {
+        if entry.kind == EntryKind::HardLink && included.contains(&entry.link_name) {
+            include_with_parents(&entry.name, &mut included);
+        }
+    }
+    // Only the topmost deleted path needs a whiteout, everything below it goes with it.
+    let mut whiteouts: HashMap<String, Vec<String>> = HashMap::new();
+    for name in old_entries.keys() {
+        let (parent, base) = split_parent(name);
+        if !new_names.contains(name.as_str()) && (parent.is_empty() || new_names.contains(parent)) {
+            whiteouts
+                .entry(parent.to_string())
+                .or_default()
+                .push(join_name(parent, &format!("{}{}", WHITEOUT_PREFIX, base)));
+            if !parent.is_empty() {
+                include_with_parents(parent, &mut included);
+            }
+        }
+    }
+
+    let file_data: HashMap<&str, &Entry> = new_entries
+        .iter()
+        .filter(|entry| entry.kind == EntryKind::File)
+        .map(|entry| (entry.name.as_str(), entry))
+        .collect();
+    let mut layer = Vec::new();
+    let mut add_whiteouts = |layer: &mut Vec<Entry>, parent: &str| {
+        if let Some(names) = whiteouts.get_mut(parent) {
+            names.sort();
+            layer.extend(names.iter().map(|name| Entry::new(name, EntryKind::File)));
+        }
+    };
+    add_whiteouts(&mut layer, "");
+    for entry in &new_entries {
+        if !included.contains(&entry.name) {
+            continue;
+        }
+        let mut entry = entry.clone();
+        if entry.kind == EntryKind::HardLink && !included.contains(&entry.link_name) {
+            // The link target is unchanged and stays in a lower layer, store the content instead.
+            if let Some(target) = file_data.get(entry.link_name.as_str()) {
+                entry.kind = EntryKind::File;
+                entry.data = target.data.clone();
+                entry.link_name.clear();
+            }
+        }
+        let directory = (entry.kind == EntryKind::Directory).then(|| entry.name.clone());
+        layer.push(entry);
+        if let Some(directory) = directory {
+            add_whiteouts(&mut layer, &directory);
+        }
+    }
+
+    let tar_buffer = write_tar_entries(&layer)?;
+    let diff_id = format!("sha256:{}", sha256_hex(&tar_buffer));
+    let layer_file_name = if compress {
+        let layer_file_name = format!("{}.tar.gz", layer_name);
+        let mut encoder =
+            GzEncoder::new(fs::File::create(&layer_file_name)?, Compression::default());
+        encoder.write_all(&tar_buffer)?;
+        encoder.finish()?;
+        layer_file_name
+    } else {
+        let layer_file_name = format!("{}.tar", layer_name);
+        fs::write(&layer_file_name, &tar_buffer)?;
+        layer_file_name
+    };
+    Ok((layer_file_name, diff_id))
+}
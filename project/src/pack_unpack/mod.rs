@@ -1,2 +1,24 @@
+pub mod ar;
+pub mod comparing;
+pub mod converting;
+pub mod cpio;
+pub mod deb;
+pub mod deleting;
+pub mod digests;
+pub mod encryption;
+pub mod entry;
+pub mod images;
+pub mod incremental;
+pub mod indexing;
+pub mod layers;
+pub mod mtree;
+pub mod ownership;
 pub mod packing;
+pub mod patterns;
+pub mod signing;
+pub mod spooling;
+pub mod transforming;
 pub mod unpacking;
+pub mod users;
+pub mod volumes;
+pub mod zip;
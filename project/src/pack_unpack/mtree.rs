@@ -0,0 +1,348 @@
+use crate::pack_unpack::digests::sha256_hex;
+use crate::pack_unpack::entry::{Entry, EntryKind};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
+
+fn type_keyword(kind: EntryKind) -> &'static str {
+    match kind {
+        EntryKind::File | EntryKind::HardLink => "file",
+        EntryKind::Symlink => "link",
+        EntryKind::CharDevice => "char",
+        EntryKind::BlockDevice => "block",
+        EntryKind::Directory => "dir",
+        EntryKind::Fifo => "fifo",
+    }
+}
+fn kind_from_keyword(keyword: &str) -> Option<EntryKind> {
+    match keyword {
+        "file" => Some(EntryKind::File),
+        "link" => Some(EntryKind::Symlink),
+        "char" => Some(EntryKind::CharDevice),
+        "block" => Some(EntryKind::BlockDevice),
+        "dir" => Some(EntryKind::Directory),
+        "fifo" => Some(EntryKind::Fifo),
+        _ => None,
+    }
+}
+/// Escapes like strsvis(3) in the octal style mtree uses, so names stay one word.
+fn escape(name: &str) -> String {
+    let mut escaped = String::new();
+    for &byte in name.as_bytes() {
+        if byte.is_ascii_graphic() && !b"\\#*?[=".contains(&byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+    escaped
+}
+fn unescape(word: &str) -> String {
+    let bytes = word.as_bytes();
+    let mut unescaped = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).filter(|digits| {
+            bytes[i] == b'\\' && digits.iter().all(|digit| (b'0'..=b'7').contains(digit))
+        });
+        match octal {
+            Some(digits) => {
+                let value = digits
+                    .iter()
+                    .fold(0u32, |value, digit| value * 8 + (digit - b'0') as u32);
+                unescaped.push(value as u8);
+                i += 4;
+            }
+            None if bytes[i] == b'\\' && i + 1 < bytes.len() => {
+                unescaped.push(bytes[i + 1]);
+                i += 2;
+            }
+            None => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).to_string()
+}
+fn spec_path(name: &str, top: &str) -> String {
+    match name.strip_prefix(top) {
+        Some("") => ".".to_string(),
+        Some(rest) if rest.starts_with('/') => format!(".{}", rest),
+        _ => format!("./{}", name),
+    }
+}
+/// Describes the archived entries relative to the packed directory, so the same spec can
+/// be fed back with --from-mtree.
+pub fn write_mtree(entries: &[Entry], mtree_file: &str) -> Result<(), io::Error> {
+    let top = entries
+        .first()
+        .map(|entry| entry.name.as_str())
+        .unwrap_or("");
+    let data: HashMap<&str, &[u8]> = entries
+        .iter()
+        .filter(|entry| entry.kind == EntryKind::File)
+        .map(|entry| (entry.name.as_str(), entry.data.as_slice()))
+        .collect();
+
+    let mut spec = String::from("#mtree\n");
+    for entry in entries {
+        spec.push_str(&escape(&spec_path(&entry.name, top)));
+        spec.push_str(&format!(
+            " type={} mode={:04o} uid={} gid={}",
+            type_keyword(entry.kind),
+            entry.mode,
+            entry.uid,
+            entry.gid
+        ));
+        if !entry.user_name.is_empty() {
+            spec.push_str(&format!(" uname={}", escape(&entry.user_name)));
+        }
+        if !entry.group_name.is_empty() {
+            spec.push_str(&format!(" gname={}", escape(&entry.group_name)));
+        }
+        spec.push_str(&format!(" time={}.0", entry.mtime));
+        match entry.kind {
+            EntryKind::File | EntryKind::HardLink => {
+                // mtree has no hard links, they are described with the content they share.
+                let content = if entry.kind == EntryKind::File {
+                    entry.data.as_slice()
+                } else {
+                    data.get(entry.link_name.as_str())
+                        .copied()
+                        .unwrap_or_default()
+                };
+                spec.push_str(&format!(
+                    " size={} sha256digest={}",
+                    content.len(),
+                    sha256_hex(content)
+                ));
+            }
+            EntryKind::Symlink => spec.push_str(&format!(" link={}", escape(&entry.link_name))),
+            EntryKind::CharDevice | EntryKind::BlockDevice => spec.push_str(&format!(
+                " device=native,{},{}",
+                entry.device.0, entry.device.1
+            )),
+            EntryKind::Directory | EntryKind::Fifo => {}
+        }
+        spec.push('\n');
+    }
+    fs::write(mtree_file, spec)
+}
+fn invalid_spec(line_number: usize, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("mtree spec line {}: {}", line_number, message),
+    )
+}
+/// Entries and their content files have to stay inside the source directory.
+fn check_spec_path(path: &str, line_number: usize) -> Result<(), io::Error> {
+    let path_ref = Path::new(path);
+    if path_ref.is_absolute()
+        || path_ref
+            .components()
+            .any(|component| component == Component::ParentDir)
+    {
+        return Err(invalid_spec(
+            line_number,
+            format!("refusing path '{}' outside the source directory", path),
+        ));
+    }
+    Ok(())
+}
+fn parse_device(value: &str) -> Option<(u64, u64)> {
+    let fields: Vec<&str> = value.split(',').collect();
+    match fields.as_slice() {
+        [_, major, minor] | [major, minor] => Some((major.parse().ok()?, minor.parse().ok()?)),
+        [number] => number
+            .parse::<u64>()
+            .ok()
+            .map(|number| (number >> 8, number & 0xff)),
+        _ => None,
+    }
+}
+fn spec_entry(
+    path: &str,
+    keywords: &HashMap<String, String>,
+    source_dir: &Path,
+    top: &str,
+    line_number: usize,
+) -> Result<Option<Entry>, io::Error> {
+    check_spec_path(path, line_number)?;
+    let keyword = |key: &str| keywords.get(key).map(String::as_str);
+    let number = |key: &str| -> Result<Option<u64>, io::Error> {
+        keyword(key)
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    invalid_spec(line_number, format!("bad {} '{}' for {}", key, value, path))
+                })
+            })
+            .transpose()
+    };
+
+    let kind = match keyword("type").map(|value| (value, kind_from_keyword(value))) {
+        Some((_, Some(kind))) => kind,
+        Some((value, None)) => {
+            println!(
+                "Warning: {}: type {} cannot be archived, skipped",
+                path, value
+            );
+            return Ok(None);
+        }
+        None => return Err(invalid_spec(line_number, format!("{} has no type", path))),
+    };
+    let name = if path == "." {
+        top.to_string()
+    } else {
+        format!("{}/{}", top, path)
+    };
+    let mut entry = Entry::new(&name, kind);
+    if let Some(mode) = keyword("mode") {
+        entry.mode = u32::from_str_radix(mode, 8)
+            .map_err(|_| invalid_spec(line_number, format!("bad mode '{}' for {}", mode, path)))?
+            & 0o7777;
+    }
+    let id = |key: &str| -> Result<u32, io::Error> {
+        u32::try_from(number(key)?.unwrap_or(0)).map_err(|_| {
+            invalid_spec(
+                line_number,
+                format!(
+                    "{} '{}' for {} is out of range",
+                    key,
+                    keyword(key).unwrap_or_default(),
+                    path
+                ),
+            )
+        })
+    };
+    entry.uid = id("uid")?;
+    entry.gid = id("gid")?;
+    entry.user_name = keyword("uname").map(unescape).unwrap_or_default();
+    entry.group_name = keyword("gname").map(unescape).unwrap_or_default();
+    if let Some(time) = keyword("time") {
+        let seconds = time.split('.').next().unwrap_or_default();
+        entry.mtime = seconds
+            .parse()
+            .map_err(|_| invalid_spec(line_number, format!("bad time '{}' for {}", time, path)))?;
+    }
+
+    match kind {
+        EntryKind::File => {
+            let content_path = keyword("contents")
+                .map(unescape)
+                .unwrap_or(path.to_string());
+            check_spec_path(&content_path, line_number)?;
+            entry.data = fs::read(source_dir.join(&content_path))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", content_path, e)))?;
+            if number("size")?.is_some_and(|size| size != entry.data.len() as u64) {
+                return Err(invalid_spec(
+                    line_number,
+                    format!("{} does not have the size given in the spec", content_path),
+                ));
+            }
+            if let Some(digest) = keyword("sha256digest").or(keyword("sha256")) {
+                if sha256_hex(&entry.data) != digest.to_ascii_lowercase() {
+                    return Err(invalid_spec(
+                        line_number,
+                        format!("{} does not match the sha256 in the spec", content_path),
+                    ));
+                }
+            }
+        }
+        EntryKind::Symlink => {
+            entry.link_name = match keyword("link") {
+                Some(link) => unescape(link),
+                None => fs::read_link(source_dir.join(path))?
+                    .to_string_lossy()
+                    .to_string(),
+            };
+        }
+        EntryKind::CharDevice | EntryKind::BlockDevice => {
+            let device = keyword("device").unwrap_or_default();
+            entry.device = parse_device(device).ok_or_else(|| {
+                invalid_spec(line_number, format!("bad device '{}' for {}", device, path))
+            })?;
+        }
+        _ => {}
+    }
+    Ok(Some(entry))
+}
+/// Builds entries whose metadata comes from an mtree spec and whose content is read from
+/// `source_dir`, stored under `top` like create_archive stores the packed directory.
+pub fn read_mtree_entries(
+    spec_file: &str,
+    source_dir: &Path,
+    top: &str,
+) -> Result<Vec<Entry>, io::Error> {
+    let spec = fs::read_to_string(spec_file)?;
+    let mut entries = Vec::new();
+    let mut defaults: HashMap<String, String> = HashMap::new();
+    let mut current_dir: Vec<String> = Vec::new();
+
+    let mut logical_line = String::new();
+    for (index, line) in spec.lines().enumerate() {
+        let line_number = index + 1;
+        if let Some(continued) = line.strip_suffix('\\') {
+            logical_line.push_str(continued);
+            logical_line.push(' ');
+            continue;
+        }
+        logical_line.push_str(line);
+        let line = std::mem::take(&mut logical_line);
+        let mut words = line.split_whitespace();
+        let first = match words.next() {
+            Some(word) if !word.starts_with('#') => word,
+            _ => continue,
+        };
+        let assignments = words.map(|word| {
+            let (key, value) = word.split_once('=').unwrap_or((word, ""));
+            (key.to_string(), value.to_string())
+        });
+
+        match first {
+            "/set" => defaults.extend(assignments),
+            "/unset" => {
+                for (key, _) in assignments {
+                    if key == "all" {
+                        defaults.clear();
+                    } else {
+                        defaults.remove(&key);
+                    }
+                }
+            }
+            ".." => {
+                current_dir.pop();
+            }
+            name => {
+                let mut keywords = defaults.clone();
+                keywords.extend(assignments);
+                let name = unescape(name);
+                // Names with a slash are full paths, plain names are relative to the
+                // current directory, which a relative directory entry descends into.
+                let path = if name.contains('/') {
+                    name.trim_start_matches("./")
+                        .trim_end_matches('/')
+                        .to_string()
+                } else if current_dir.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}/{}", current_dir.join("/"), name)
+                };
+                if !name.contains('/')
+                    && name != "."
+                    && keywords.get("type").map(String::as_str) == Some("dir")
+                {
+                    current_dir.push(name);
+                }
+                if keywords.contains_key("optional") && !source_dir.join(&path).exists() {
+                    continue;
+                }
+                if let Some(entry) = spec_entry(&path, &keywords, source_dir, top, line_number)? {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,280 @@
+use crate::pack_unpack::patterns::member_matches;
+use crate::pack_unpack::users::IdResolver;
+use std::fs;
+use std::io;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Owner {
+    pub name: String,
+    pub id: u32,
+}
+#[derive(Clone, Debug, PartialEq)]
+enum ModeClause {
+    Absolute(u32),
+    Symbolic {
+        who: u32,
+        operator: char,
+        permissions: String,
+    },
+}
+/// A chmod-style mode expression such as `u+rwX,go-w` or `0644`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModeChange {
+    clauses: Vec<ModeClause>,
+}
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnershipRule {
+    pattern: String,
+    owner: Option<Owner>,
+    group: Option<Owner>,
+    mode: Option<ModeChange>,
+}
+#[derive(Clone, Debug, Default)]
+pub struct OwnershipOptions {
+    pub owner: Option<Owner>,
+    pub group: Option<Owner>,
+    pub numeric_owner: bool,
+    pub mode: Option<ModeChange>,
+    pub rules: Vec<OwnershipRule>,
+}
+/// What ends up in a header: the ids, names and permission bits of one member.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileOwnership {
+    pub uid: u32,
+    pub gid: u32,
+    pub user_name: String,
+    pub group_name: String,
+    pub mode: u32,
+}
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+/// Parses NAME, NAME:ID, ID or +ID like tar's --owner and --group, looking up whichever half
+/// is missing in the user or the group database.
+fn parse_id_spec(
+    spec: &str,
+    resolver: &mut IdResolver,
+    is_group: bool,
+) -> Result<Owner, io::Error> {
+    let spec = spec.trim();
+    if let Some((name, id)) = spec.split_once(':') {
+        let id = id
+            .parse()
+            .map_err(|_| invalid_input(format!("invalid id in '{}'", spec)))?;
+        return Ok(Owner {
+            name: name.to_string(),
+            id,
+        });
+    }
+    let numeric = spec.strip_prefix('+').unwrap_or(spec);
+    if !numeric.is_empty() && numeric.bytes().all(|b| b.is_ascii_digit()) {
+        let id = numeric
+            .parse()
+            .map_err(|_| invalid_input(format!("invalid id '{}'", spec)))?;
+        let name = if is_group {
+            resolver.group_name(id)
+        } else {
+            resolver.user_name(id)
+        };
+        return Ok(Owner {
+            name: name.unwrap_or_default(),
+            id,
+        });
+    }
+    let id = if is_group {
+        resolver.group_id(spec)
+    } else {
+        resolver.user_id(spec)
+    };
+    match id {
+        Some(id) => Ok(Owner {
+            name: spec.to_string(),
+            id,
+        }),
+        None => Err(invalid_input(format!(
+            "'{}' is unknown on this system, give the id as {}:<id>",
+            spec, spec
+        ))),
+    }
+}
+pub fn parse_owner(spec: &str, resolver: &mut IdResolver) -> Result<Owner, io::Error> {
+    parse_id_spec(spec, resolver, false)
+}
+pub fn parse_group(spec: &str, resolver: &mut IdResolver) -> Result<Owner, io::Error> {
+    parse_id_spec(spec, resolver, true)
+}
+impl ModeChange {
+    pub fn parse(expression: &str) -> Result<Self, io::Error> {
+        let invalid = || invalid_input(format!("invalid mode '{}'", expression));
+        let mut clauses = Vec::new();
+        for clause in expression.split(',') {
+            if !clause.is_empty() && clause.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+                let mode = u32::from_str_radix(clause, 8).map_err(|_| invalid())?;
+                if mode > 0o7777 {
+                    return Err(invalid());
+                }
+                clauses.push(ModeClause::Absolute(mode));
+                continue;
+            }
+            let operator_start = clause.find(['+', '-', '=']).ok_or_else(invalid)?;
+            let mut who = 0;
+            for class in clause[..operator_start].chars() {
+                who |= match class {
+                    'u' => 0o4700,
+                    'g' => 0o2070,
+                    'o' => 0o1007,
+                    'a' => 0o7777,
+                    _ => return Err(invalid()),
+                };
+            }
+            if who == 0 {
+                who = 0o7777;
+            }
+            // Several operations may follow one another, as in u+r-x.
+            let mut rest = &clause[operator_start..];
+            while let Some(operator) = rest.chars().next() {
+                let end = rest[1..]
+                    .find(['+', '-', '='])
+                    .map_or(rest.len(), |end| end + 1);
+                let permissions = &rest[1..end];
+                let is_copy = matches!(permissions, "u" | "g" | "o");
+                if !is_copy && !permissions.chars().all(|c| "rwxXst".contains(c)) {
+                    return Err(invalid());
+                }
+                clauses.push(ModeClause::Symbolic {
+                    who,
+                    operator,
+                    permissions: permissions.to_string(),
+                });
+                rest = &rest[end..];
+            }
+        }
+        Ok(ModeChange { clauses })
+    }
+    pub fn apply(&self, mode: u32, is_directory: bool) -> u32 {
+        let mut mode = mode;
+        for clause in &self.clauses {
+            match clause {
+                ModeClause::Absolute(absolute) => mode = *absolute,
+                ModeClause::Symbolic {
+                    who,
+                    operator,
+                    permissions,
+                } => {
+                    let bits = match permissions.as_str() {
+                        "u" => ((mode >> 6) & 7) * 0o111,
+                        "g" => ((mode >> 3) & 7) * 0o111,
+                        "o" => (mode & 7) * 0o111,
+                        permissions => permissions.chars().fold(0, |bits, permission| {
+                            bits | match permission {
+                                'r' => 0o444,
+                                'w' => 0o222,
+                                'x' => 0o111,
+                                'X' if is_directory || mode & 0o111 != 0 => 0o111,
+                                's' => 0o6000,
+                                't' => 0o1000,
+                                _ => 0,
+                            }
+                        }),
+                    } & who;
+                    match operator {
+                        '+' => mode |= bits,
+                        '-' => mode &= !bits,
+                        _ => mode = (mode & !who) | bits,
+                    }
+                }
+            }
+        }
+        mode & 0o7777
+    }
+}
+/// Reads rule lines of the form `<pattern> [owner=..] [group=..] [mode=..]`; later rules
+/// win over earlier ones for the members they both match.
+pub fn load_rules(rule_file: &str) -> Result<Vec<OwnershipRule>, io::Error> {
+    let content = fs::read_to_string(rule_file)?;
+    let mut resolver = IdResolver::default();
+    let mut rules = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let pattern = match words.next() {
+            Some(word) if !word.starts_with('#') => word,
+            _ => continue,
+        };
+        let invalid = |message: String| {
+            invalid_input(format!("{} line {}: {}", rule_file, index + 1, message))
+        };
+        let mut rule = OwnershipRule {
+            pattern: pattern.to_string(),
+            owner: None,
+            group: None,
+            mode: None,
+        };
+        for word in words {
+            match word.split_once('=') {
+                Some(("owner", owner)) => {
+                    rule.owner = Some(
+                        parse_owner(owner, &mut resolver).map_err(|e| invalid(e.to_string()))?,
+                    )
+                }
+                Some(("group", group)) => {
+                    rule.group = Some(
+                        parse_group(group, &mut resolver).map_err(|e| invalid(e.to_string()))?,
+                    )
+                }
+                Some(("mode", mode)) => {
+                    rule.mode = Some(ModeChange::parse(mode).map_err(|e| invalid(e.to_string()))?)
+                }
+                _ => return Err(invalid(format!("unknown setting '{}'", word))),
+            }
+        }
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+fn apply_overrides(
+    ownership: &mut FileOwnership,
+    owner: &Option<Owner>,
+    group: &Option<Owner>,
+    mode: &Option<ModeChange>,
+    is_directory: bool,
+) {
+    if let Some(owner) = owner {
+        ownership.uid = owner.id;
+        ownership.user_name = owner.name.clone();
+    }
+    if let Some(group) = group {
+        ownership.gid = group.id;
+        ownership.group_name = group.name.clone();
+    }
+    if let Some(mode) = mode {
+        ownership.mode = mode.apply(ownership.mode, is_directory);
+    }
+}
+impl OwnershipOptions {
+    pub fn apply(&self, member_name: &str, is_directory: bool, ownership: &mut FileOwnership) {
+        apply_overrides(
+            ownership,
+            &self.owner,
+            &self.group,
+            &self.mode,
+            is_directory,
+        );
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| member_matches(&rule.pattern, member_name))
+        {
+            apply_overrides(
+                ownership,
+                &rule.owner,
+                &rule.group,
+                &rule.mode,
+                is_directory,
+            );
+        }
+        if self.numeric_owner {
+            ownership.user_name.clear();
+            ownership.group_name.clear();
+        }
+    }
+}
@@ -1,11 +1,82 @@
+use crate::pack_unpack::converting::{write_entries, ArchiveFormat};
+use crate::pack_unpack::digests::{
+    manifest_line, pax_record, sha256_hex, DIGEST_KEY, MANIFEST_NAME,
+};
+use crate::pack_unpack::encryption::EncryptingWriter;
+use crate::pack_unpack::entry::{read_tar_entries, write_tar_entries, Entry};
+use crate::pack_unpack::incremental::{dumpdir_entry, IncrementalState, Snapshot, SnapshotRecord};
+use crate::pack_unpack::indexing::{build_index, write_index, write_seekable_gzip};
+use crate::pack_unpack::mtree::{read_mtree_entries, write_mtree};
+use crate::pack_unpack::ownership::{FileOwnership, OwnershipOptions};
+use crate::pack_unpack::transforming::{NameKind, Transforms};
+use crate::pack_unpack::users::IdResolver;
+use crate::pack_unpack::volumes::{remove_stale_volumes, write_volumes};
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use std::collections::HashMap;
 use std::fs;
-use std::fs::symlink_metadata;
-use std::io::{Read, Write};
+use std::fs::{symlink_metadata, Metadata};
+use std::io::{BufWriter, Read, Write};
 use std::os::unix::fs::{FileTypeExt, MetadataExt};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DigestMode {
+    #[default]
+    None,
+    Pax,
+    Manifest,
+}
+#[derive(Debug, Default)]
+pub struct PackOptions {
+    pub compress: bool,
+    pub digests: DigestMode,
+    pub passphrase: Option<String>,
+    pub snapshot_file: Option<String>,
+    pub volume_size: Option<u64>,
+    pub index: bool,
+    pub seekable: bool,
+    pub format: ArchiveFormat,
+    pub mtree_file: Option<String>,
+    pub from_mtree: Option<String>,
+    pub ownership: OwnershipOptions,
+    pub transforms: Transforms,
+    pub roots: Vec<PackRoot>,
+    pub dereference: Dereference,
+    pub one_file_system: bool,
+    pub hard_dereference: bool,
+}
+/// Which symlinks are archived as the file or directory they point to.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Dereference {
+    #[default]
+    Never,
+    CommandLine,
+    Always,
+}
+/// A path to archive besides the packed directory; it is stored under its name relative
+/// to `base`, the -C directory it was given after.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackRoot {
+    pub base: PathBuf,
+    pub path: PathBuf,
+}
+/// The member a file with several links was first stored as, and how many of its other
+/// links are still to come.
+#[derive(Debug)]
+struct LinkTarget {
+    name: String,
+    links_left: u64,
+}
+#[derive(Debug, Default)]
+struct PackState {
+    inode_map: HashMap<(u64, u64), LinkTarget>,
+    manifest: String,
+    incremental: Option<IncrementalState>,
+    resolver: IdResolver,
+    root_device: u64,
+    // (dev, ino) of the directories being archived, to stop at symlinks leading back up.
+    open_directories: Vec<(u64, u64)>,
+}
 #[derive(Debug)]
 pub struct UStarHeader {
     name: [u8; 100],
@@ -71,6 +142,24 @@ impl UStarHeader {
 
         bytes
     }
+    fn set_type_and_size(&mut self, type_flag: u8, size: usize) {
+        self.type_flag[0] = type_flag;
+        self.size.fill(0);
+        let file_size = format!("{:0>11o}\0", size);
+        self.size[..file_size.len()].copy_from_slice(file_size.as_bytes());
+
+        self.checksum.fill(b' ');
+        let checksum_str = format!("{:06o}\0", calculate_checksum(self));
+        self.checksum.fill(0);
+        self.checksum[..checksum_str.len()].copy_from_slice(checksum_str.as_bytes());
+    }
+}
+fn archive_path_name(path: &Path, parent_path: &Path) -> String {
+    path.strip_prefix(parent_path.to_str().unwrap())
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
 }
 fn calculate_checksum(header: &UStarHeader) -> u32 {
     let mut checksum: u32 = 0;
@@ -82,17 +171,21 @@ fn calculate_checksum(header: &UStarHeader) -> u32 {
 fn create_header(
     path: &Path,
     parent_path: &Path,
-    inode_map: &mut HashMap<u64, String>,
+    metadata: &Metadata,
+    inode_map: &HashMap<(u64, u64), LinkTarget>,
+    ownership_options: &OwnershipOptions,
+    transforms: &Transforms,
+    resolver: &mut IdResolver,
 ) -> Result<UStarHeader, std::io::Error> {
     let mut header = UStarHeader::new();
-    let metadata = symlink_metadata(path)?;
     let path_name = path
         .strip_prefix(parent_path.to_str().unwrap())
         .unwrap()
         .to_str()
         .unwrap();
+    let member_name = transforms.apply(path_name, NameKind::Regular);
 
-    let path_bytes = path_name.as_bytes();
+    let path_bytes = member_name.as_bytes();
     if metadata.is_dir() {
         let mut dir_bytes = path_bytes.to_vec();
         dir_bytes.push(b'/');
@@ -111,8 +204,12 @@ fn create_header(
         header.name[..path_bytes.len()].copy_from_slice(path_bytes);
     }
 
-    if let Some(original_path) = inode_map.get(&metadata.ino()) {
+    let link_target = inode_map
+        .get(&(metadata.dev(), metadata.ino()))
+        .filter(|_| metadata.is_file());
+    if let Some(link_target) = link_target {
         header.type_flag[0] = b'1';
+        let original_path = transforms.apply(&link_target.name, NameKind::HardLinkTarget);
         let link_bytes = original_path.as_bytes();
         header.link_name[..link_bytes.len()].copy_from_slice(link_bytes);
     } else if metadata.is_file() {
@@ -120,12 +217,13 @@ fn create_header(
     } else if metadata.is_symlink() {
         header.type_flag[0] = b'2';
         let link_target = fs::read_link(path)?;
-        let link_target_str = link_target.to_str().unwrap();
+        let link_target_str =
+            transforms.apply(link_target.to_str().unwrap(), NameKind::SymlinkTarget);
         let link_bytes = link_target_str.as_bytes();
         header.link_name[..link_bytes.len()].copy_from_slice(link_bytes);
     } else if metadata.file_type().is_block_device() || metadata.file_type().is_char_device() {
         let device_major = format!("{:0>7o}\0", metadata.rdev() >> 8);
-        let device_minor = format!("{:0>7o}\0", metadata.rdev());
+        let device_minor = format!("{:0>7o}\0", metadata.rdev() & 0xff);
         header.device_major[..device_major.len()].copy_from_slice(device_major.as_bytes());
         header.device_minor[..device_minor.len()].copy_from_slice(device_minor.as_bytes());
         header.type_flag[0] = if metadata.file_type().is_block_device() {
@@ -138,40 +236,37 @@ fn create_header(
     } else if metadata.file_type().is_fifo() {
         header.type_flag[0] = b'6';
     } else {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Unsupported file type",
-        ));
+        return Err(std::io::Error::other("Unsupported file type"));
     }
 
-    let mode = format!("{:0>7o}\0", metadata.mode() & 0o777);
+    let mut ownership = FileOwnership {
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        user_name: String::new(),
+        group_name: String::new(),
+        mode: metadata.mode() & 0o777,
+    };
+    if !ownership_options.numeric_owner {
+        ownership.user_name = resolver.user_name(metadata.uid()).unwrap_or_default();
+        ownership.group_name = resolver.group_name(metadata.gid()).unwrap_or_default();
+    }
+    ownership_options.apply(path_name, metadata.is_dir(), &mut ownership);
+
+    let mode = format!("{:0>7o}\0", ownership.mode);
     header.mode[..mode.len()].copy_from_slice(mode.as_bytes());
 
-    let uid = format!("{:0>7o}\0", metadata.uid());
-    let gid = format!("{:0>7o}\0", metadata.gid());
+    let uid = format!("{:0>7o}\0", ownership.uid);
+    let gid = format!("{:0>7o}\0", ownership.gid);
     header.uid[..uid.len()].copy_from_slice(uid.as_bytes());
     header.gid[..gid.len()].copy_from_slice(gid.as_bytes());
 
-    let mut uname: String = "".to_string();
-    let mut content = fs::read_to_string("/etc/passwd")?;
-    for line in content.lines() {
-        let parts: Vec<&str> = line.split(':').collect();
-        if parts.len() >= 3 && metadata.uid() == parts[2].parse::<u32>().unwrap() {
-            uname = parts[0].to_string();
-        }
-    }
-    let mut gname: String = "".to_string();
-    content = fs::read_to_string("/etc/group")?;
-    for line in content.lines() {
-        let parts: Vec<&str> = line.split(':').collect();
-        if parts.len() >= 3 && metadata.gid() == parts[2].parse::<u32>().unwrap() {
-            gname = parts[0].to_string();
-        }
-    }
-    header.user_name[..uname.len()].copy_from_slice(uname.as_bytes());
-    header.group_name[..gname.len()].copy_from_slice(gname.as_bytes());
+    let uname = &ownership.user_name.as_bytes()[..ownership.user_name.len().min(32)];
+    let gname = &ownership.group_name.as_bytes()[..ownership.group_name.len().min(32)];
+    header.user_name[..uname.len()].copy_from_slice(uname);
+    header.group_name[..gname.len()].copy_from_slice(gname);
 
-    let size = if metadata.is_dir() || metadata.is_file() {
+    // Hard links carry no data, the target member does.
+    let size = if metadata.is_dir() || (metadata.is_file() && link_target.is_none()) {
         metadata.len()
     } else {
         0
@@ -192,87 +287,476 @@ fn create_header(
 
     Ok(header)
 }
+fn create_data_header(name: &str, type_flag: u8, size: usize, mtime: i64) -> UStarHeader {
+    let mut header = UStarHeader::new();
+    let name_bytes = name.as_bytes();
+    let name_len = name_bytes.len().min(100);
+    header.name[..name_len].copy_from_slice(&name_bytes[..name_len]);
+    header.type_flag[0] = type_flag;
+
+    let mode = format!("{:0>7o}\0", 0o644);
+    header.mode[..mode.len()].copy_from_slice(mode.as_bytes());
+    let zero = format!("{:0>7o}\0", 0);
+    header.uid[..zero.len()].copy_from_slice(zero.as_bytes());
+    header.gid[..zero.len()].copy_from_slice(zero.as_bytes());
+    let file_size = format!("{:0>11o}\0", size);
+    header.size[..file_size.len()].copy_from_slice(file_size.as_bytes());
+    let mtime_str = format!("{:o}\0", mtime);
+    header.modification_time[..mtime_str.len()].copy_from_slice(mtime_str.as_bytes());
+
+    header.ustar.copy_from_slice(b"ustar\0");
+    header.version.copy_from_slice(b"00");
+
+    header.checksum.fill(b' ');
+    let checksum_str = format!("{:06o}\0", calculate_checksum(&header));
+    header.checksum[..checksum_str.len()].copy_from_slice(checksum_str.as_bytes());
+
+    header
+}
+fn write_member(
+    tar_buffer: &mut Vec<u8>,
+    header: &UStarHeader,
+    data: &[u8],
+) -> Result<(), std::io::Error> {
+    tar_buffer.write_all(&header.as_bytes())?;
+    tar_buffer.write_all(data)?;
+    let padding = (512 - (data.len() % 512)) % 512;
+    tar_buffer.write_all(&vec![0; padding])?;
+    Ok(())
+}
+/// The metadata a path is archived with: that of the symlink target when following links,
+/// and of the link itself for dangling ones.
+fn member_metadata(path: &Path, follow: bool) -> Result<Metadata, std::io::Error> {
+    let metadata = symlink_metadata(path)?;
+    if !follow || !metadata.is_symlink() {
+        return Ok(metadata);
+    }
+    match fs::metadata(path) {
+        Ok(target_metadata) => Ok(target_metadata),
+        Err(e) => {
+            println!("Warning: {}: {}, archived as a symlink", path.display(), e);
+            Ok(metadata)
+        }
+    }
+}
 fn add_to_archive(
     file_path: &Path,
     parent_path: &Path,
     tar_buffer: &mut Vec<u8>,
-    inode_map: &mut HashMap<u64, String>,
+    state: &mut PackState,
+    options: &PackOptions,
+    is_root: bool,
 ) -> Result<(), std::io::Error> {
-    if symlink_metadata(file_path)?.is_file() {
-        let metadata = symlink_metadata(file_path)?;
-        let inode = metadata.ino();
+    let path_name = archive_path_name(file_path, parent_path);
+    let follow = match options.dereference {
+        Dereference::Never => false,
+        Dereference::CommandLine => is_root,
+        Dereference::Always => true,
+    };
+    let metadata = member_metadata(file_path, follow)?;
+    if is_root {
+        state.root_device = metadata.dev();
+    }
+    if let Some(incremental) = &mut state.incremental {
+        incremental
+            .current
+            .records
+            .insert(path_name.clone(), SnapshotRecord::from_metadata(&metadata));
+        if !metadata.is_dir() && incremental.previous.is_unchanged(&path_name, &metadata) {
+            return Ok(());
+        }
+    }
 
-        if inode_map.contains_key(&inode) {
-            let header = create_header(file_path, parent_path, inode_map)?;
+    if metadata.is_file() {
+        let inode = (metadata.dev(), metadata.ino());
+
+        if state.inode_map.contains_key(&inode) {
+            let header = create_header(
+                file_path,
+                parent_path,
+                &metadata,
+                &state.inode_map,
+                &options.ownership,
+                &options.transforms,
+                &mut state.resolver,
+            )?;
             tar_buffer.write_all(&header.as_bytes())?;
+            // Once every link has been stored the inode cannot come up again.
+            if let Some(link_target) = state.inode_map.get_mut(&inode) {
+                link_target.links_left -= 1;
+                if link_target.links_left == 0 {
+                    state.inode_map.remove(&inode);
+                }
+            }
         } else {
-            let header = create_header(file_path, parent_path, inode_map)?;
-            tar_buffer.write_all(&header.as_bytes())?;
-
-            inode_map.insert(
-                inode,
-                file_path
-                    .strip_prefix(parent_path.to_str().unwrap())
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-            );
+            let header = create_header(
+                file_path,
+                parent_path,
+                &metadata,
+                &state.inode_map,
+                &options.ownership,
+                &options.transforms,
+                &mut state.resolver,
+            )?;
             let mut file = fs::File::open(file_path)?;
             let mut buffer = Vec::new();
             file.read_to_end(&mut buffer)?;
+
+            match options.digests {
+                DigestMode::Pax => {
+                    let records = pax_record(DIGEST_KEY, &sha256_hex(&buffer));
+                    let file_name = file_path.file_name().unwrap().to_str().unwrap();
+                    let pax_header = create_data_header(
+                        &format!("PaxHeaders/{}", file_name),
+                        b'x',
+                        records.len(),
+                        metadata.mtime(),
+                    );
+                    write_member(tar_buffer, &pax_header, records.as_bytes())?;
+                }
+                DigestMode::Manifest => {
+                    state.manifest.push_str(&manifest_line(
+                        &sha256_hex(&buffer),
+                        &options.transforms.apply(&path_name, NameKind::Regular),
+                    ));
+                }
+                DigestMode::None => {}
+            }
+
+            tar_buffer.write_all(&header.as_bytes())?;
+            if metadata.nlink() > 1 && !options.hard_dereference {
+                state.inode_map.insert(
+                    inode,
+                    LinkTarget {
+                        name: path_name,
+                        links_left: metadata.nlink() - 1,
+                    },
+                );
+            }
             tar_buffer.write_all(&buffer)?;
 
             let padding = (512 - (metadata.len() % 512)) % 512;
             tar_buffer.write_all(&vec![0; padding as usize])?;
         }
-    } else if symlink_metadata(file_path)?.is_dir() {
-        let header = create_header(file_path, parent_path, inode_map)?;
-        tar_buffer.write_all(&header.as_bytes())?;
+    } else if metadata.is_dir() {
+        let directory_id = (metadata.dev(), metadata.ino());
+        if state.open_directories.contains(&directory_id) {
+            println!(
+                "Warning: {}: symlink loop back to a parent directory, skipped",
+                path_name
+            );
+            return Ok(());
+        }
+        let mut header = create_header(
+            file_path,
+            parent_path,
+            &metadata,
+            &state.inode_map,
+            &options.ownership,
+            &options.transforms,
+            &mut state.resolver,
+        )?;
+        let mut children = Vec::new();
+        // Like tar, a mount point is archived but not what is mounted on it.
+        if !options.one_file_system || metadata.dev() == state.root_device {
+            for entry in fs::read_dir(file_path)? {
+                children.push(entry?.path());
+            }
+        }
+
+        if let Some(incremental) = &state.incremental {
+            let mut dumpdir = Vec::new();
+            for child in &children {
+                let child_metadata =
+                    member_metadata(child, options.dereference == Dereference::Always)?;
+                let child_name = child.file_name().unwrap().to_str().unwrap();
+                let control = if child_metadata.is_dir() {
+                    'D'
+                } else if incremental
+                    .previous
+                    .is_unchanged(&archive_path_name(child, parent_path), &child_metadata)
+                {
+                    'N'
+                } else {
+                    'Y'
+                };
+                dumpdir.extend_from_slice(&dumpdir_entry(control, child_name));
+            }
+            dumpdir.push(0);
+            header.set_type_and_size(b'D', dumpdir.len());
+            write_member(tar_buffer, &header, &dumpdir)?;
+        } else {
+            tar_buffer.write_all(&header.as_bytes())?;
+        }
 
-        for entry in fs::read_dir(file_path)? {
-            let entry = entry?;
-            let path = entry.path();
-            add_to_archive(&path, parent_path, tar_buffer, inode_map)?;
+        state.open_directories.push(directory_id);
+        for path in children {
+            add_to_archive(&path, parent_path, tar_buffer, state, options, false)?;
         }
+        state.open_directories.pop();
     } else {
-        let header = create_header(file_path, parent_path, inode_map)?;
+        let header = create_header(
+            file_path,
+            parent_path,
+            &metadata,
+            &state.inode_map,
+            &options.ownership,
+            &options.transforms,
+            &mut state.resolver,
+        )?;
         tar_buffer.write_all(&header.as_bytes())?;
     }
     Ok(())
 }
+fn write_archive(
+    tar_buffer: &[u8],
+    archive_file_name: &str,
+    options: &PackOptions,
+) -> Result<(), std::io::Error> {
+    let archive_file = BufWriter::new(fs::File::create(archive_file_name)?);
+    match &options.passphrase {
+        Some(passphrase) => {
+            let mut encrypter = EncryptingWriter::new(archive_file, passphrase)?;
+            if options.compress {
+                let mut encoder = GzEncoder::new(&mut encrypter, Compression::default());
+                encoder.write_all(tar_buffer)?;
+                encoder.finish()?;
+            } else {
+                encrypter.write_all(tar_buffer)?;
+            }
+            encrypter.finish()?.flush()?;
+        }
+        None => {
+            if options.compress {
+                let mut encoder = GzEncoder::new(archive_file, Compression::default());
+                encoder.write_all(tar_buffer)?;
+                encoder.finish()?.flush()?;
+            } else {
+                let mut archive_file = archive_file;
+                archive_file.write_all(tar_buffer)?;
+                archive_file.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
+impl PackRoot {
+    /// Resolves `name` against `base_dir`. Absolute names are stored without their leading
+    /// slash, and a name that is the base directory itself stands for everything in it.
+    pub fn resolve(base_dir: &str, name: &str) -> Result<Vec<PackRoot>, std::io::Error> {
+        let name = Path::new(name);
+        let base = if name.is_absolute() {
+            PathBuf::from("/")
+        } else {
+            PathBuf::from(base_dir)
+        };
+        if name.components().any(|c| c == Component::ParentDir) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{}: names with .. cannot be archived", name.display()),
+            ));
+        }
+        if !name.components().any(|c| matches!(c, Component::Normal(_))) {
+            let mut roots = Vec::new();
+            for entry in fs::read_dir(base.join(name))? {
+                roots.push(PackRoot {
+                    base: base.clone(),
+                    path: base.join(entry?.file_name()),
+                });
+            }
+            roots.sort_by(|a, b| a.path.cmp(&b.path));
+            return Ok(roots);
+        }
+        let path = base.join(name);
+        if let Err(e) = symlink_metadata(&path) {
+            return Err(std::io::Error::new(
+                e.kind(),
+                format!("{}: {}", path.display(), e),
+            ));
+        }
+        Ok(vec![PackRoot { path, base }])
+    }
+}
+/// Reads the names of a -T list file, or of stdin for `-`. Names are separated by NUL
+/// bytes when there are any, as written by `find -print0`, and by newlines otherwise.
+pub fn read_file_list(list_file: &str) -> Result<Vec<String>, std::io::Error> {
+    let mut content = Vec::new();
+    if list_file == "-" {
+        std::io::stdin().read_to_end(&mut content)?;
+    } else {
+        content = fs::read(list_file)?;
+    }
+    let separator = if content.contains(&0) { b'\0' } else { b'\n' };
+    Ok(content
+        .split(|&byte| byte == separator)
+        .map(|name| {
+            String::from_utf8_lossy(name)
+                .trim_end_matches('\r')
+                .to_string()
+        })
+        .filter(|name| !name.is_empty())
+        .collect())
+}
+pub fn read_tree_entries(base_path_name: &str) -> Result<Vec<Entry>, std::io::Error> {
+    let path = Path::new(base_path_name);
+    let mut tar_buffer = Vec::new();
+    add_to_archive(
+        path,
+        path.parent().unwrap(),
+        &mut tar_buffer,
+        &mut PackState::default(),
+        &PackOptions::default(),
+        true,
+    )?;
+    tar_buffer.extend_from_slice(&[0; 1024]);
+    read_tar_entries(&mut tar_buffer.as_slice())
+}
 pub fn create_archive(
     base_path_name: &str,
     archive_name: &str,
-    compress: bool,
-) -> Result<(), std::io::Error> {
-    let archive_file_name = if compress {
+    options: &PackOptions,
+) -> Result<String, std::io::Error> {
+    let is_tar = matches!(options.format, ArchiveFormat::Tar | ArchiveFormat::TarGz);
+    if !is_tar
+        && (options.compress
+            || options.passphrase.is_some()
+            || options.snapshot_file.is_some()
+            || options.volume_size.is_some()
+            || options.index)
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "-c, --encrypt, --listed-incremental, --volume-size and --index only apply to tar archives",
+        ));
+    }
+
+    let mut archive_file_name = if options.format == ArchiveFormat::Zip {
+        format!("{}.zip", archive_name)
+    } else if !is_tar {
+        format!("{}.cpio", archive_name)
+    } else if options.compress {
         format!("{}.tar.gz", archive_name)
     } else {
         format!("{}.tar", archive_name)
     };
+    if options.passphrase.is_some() {
+        archive_file_name.push_str(".enc");
+    }
+    if options.seekable && (!options.compress || options.passphrase.is_some()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "seekable archives must be compressed (-c) and cannot be encrypted",
+        ));
+    }
+    if options.index && options.compress && !options.seekable {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "an index for a compressed archive needs --seekable",
+        ));
+    }
+    if options.index && options.volume_size.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "an index can only be written for single-volume archives",
+        ));
+    }
+    if options.from_mtree.is_some()
+        && (options.snapshot_file.is_some()
+            || options.digests != DigestMode::None
+            || !options.roots.is_empty())
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--from-mtree cannot be combined with --listed-incremental, --digest, -C or -T",
+        ));
+    }
+    if options.volume_size.is_some() && (options.compress || options.passphrase.is_some()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "multi-volume archives cannot be compressed or encrypted",
+        ));
+    }
 
     let mut tar_buffer = Vec::new();
-    let mut inode_map: HashMap<u64, String> = HashMap::new();
+    let mut state = PackState::default();
+    if let Some(snapshot_file) = &options.snapshot_file {
+        state.incremental = Some(IncrementalState {
+            previous: Snapshot::load(snapshot_file)?,
+            current: Snapshot::default(),
+        });
+    }
 
-    let path = Path::new(base_path_name);
-    let parent = path.parent().unwrap();
-    match add_to_archive(path, parent, &mut tar_buffer, &mut inode_map) {
-        Ok(()) => {
-            tar_buffer.extend_from_slice(&[0; 512]);
-            tar_buffer.extend_from_slice(&[0; 512]);
+    // The packed directory is stored under its own name, like the first of the roots.
+    let mut roots = Vec::new();
+    if !base_path_name.is_empty() {
+        let path = Path::new(base_path_name);
+        roots.push(PackRoot {
+            base: path.parent().unwrap().to_path_buf(),
+            path: path.to_path_buf(),
+        });
+    }
+    roots.extend(options.roots.iter().cloned());
+    if roots.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "nothing to archive",
+        ));
+    }
+    if let Some(spec_file) = &options.from_mtree {
+        let PackRoot { base, path } = &roots[0];
+        let mut entries = read_mtree_entries(spec_file, path, &archive_path_name(path, base))?;
+        for entry in &mut entries {
+            options.transforms.apply_to_entry(entry);
+        }
+        tar_buffer = write_tar_entries(&entries)?;
+    } else {
+        for root in &roots {
+            add_to_archive(
+                &root.path,
+                &root.base,
+                &mut tar_buffer,
+                &mut state,
+                options,
+                true,
+            )?;
+        }
+        if options.digests == DigestMode::Manifest {
+            let mtime = symlink_metadata(&roots[0].path)?.mtime();
+            let header = create_data_header(MANIFEST_NAME, b'0', state.manifest.len(), mtime);
+            write_member(&mut tar_buffer, &header, state.manifest.as_bytes())?;
+        }
+        tar_buffer.extend_from_slice(&[0; 512]);
+        tar_buffer.extend_from_slice(&[0; 512]);
+    }
+    if let Some(mtree_file) = &options.mtree_file {
+        write_mtree(&read_tar_entries(&mut tar_buffer.as_slice())?, mtree_file)?;
+    }
 
-            if compress {
-                let archive_file = fs::File::create(archive_file_name)?;
-                let mut encoder = GzEncoder::new(archive_file, Compression::default());
-                encoder.write_all(&tar_buffer)?;
-                encoder.finish()?;
-            } else {
-                let mut archive_file = fs::File::create(archive_file_name)?;
-                archive_file.write_all(&tar_buffer)?;
-            }
-            Ok(())
+    if !is_tar {
+        let entries = read_tar_entries(&mut tar_buffer.as_slice())?;
+        write_entries(&entries, &archive_file_name, options.format)?;
+    } else if let Some(volume_size) = options.volume_size {
+        let volumes = write_volumes(&tar_buffer, &archive_file_name, volume_size)?;
+        remove_stale_volumes(&archive_file_name, volumes.len() + 1)?;
+        archive_file_name = format!(
+            "{} ... {} ({} volumes)",
+            volumes[0],
+            volumes[volumes.len() - 1],
+            volumes.len()
+        );
+    } else if options.seekable {
+        let mut entries = build_index(&mut tar_buffer.as_slice())?;
+        write_seekable_gzip(&tar_buffer, &archive_file_name, &mut entries)?;
+        write_index(&archive_file_name, &entries)?;
+    } else {
+        write_archive(&tar_buffer, &archive_file_name, options)?;
+        if options.index && options.passphrase.is_none() {
+            let entries = build_index(&mut tar_buffer.as_slice())?;
+            write_index(&archive_file_name, &entries)?;
         }
-        Err(e) => Err(e),
     }
+    if let (Some(snapshot_file), Some(incremental)) = (&options.snapshot_file, &state.incremental) {
+        incremental.current.save(snapshot_file)?;
+    }
+    Ok(archive_file_name)
 }
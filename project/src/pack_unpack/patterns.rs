@@ -0,0 +1,37 @@
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+pub fn member_matches(pattern: &str, member_name: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    let member_name = member_name.trim_end_matches('/');
+    if member_name == pattern || member_name.starts_with(&format!("{}/", pattern)) {
+        return true;
+    }
+    if pattern.contains(['*', '?']) {
+        return wildcard_match(pattern, member_name);
+    }
+    false
+}
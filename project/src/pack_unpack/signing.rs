@@ -0,0 +1,128 @@
+use crate::pack_unpack::volumes::is_first_volume;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+
+const SIGNATURE_CONTEXT: &[u8] = b"tararchiver-signature-v1\0";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+fn hex_digit(byte: u8) -> Result<u8, io::Error> {
+    match byte {
+        b'0'..=b'9' => Ok(byte - b'0'),
+        b'a'..=b'f' => Ok(byte - b'a' + 10),
+        b'A'..=b'F' => Ok(byte - b'A' + 10),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid hex data",
+        )),
+    }
+}
+fn from_hex(text: &str) -> Result<Vec<u8>, io::Error> {
+    let bytes = text.trim().as_bytes();
+    if !bytes.len().is_multiple_of(2) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "hex data has odd length",
+        ));
+    }
+    bytes
+        .chunks(2)
+        .map(|pair| Ok((hex_digit(pair[0])? << 4) | hex_digit(pair[1])?))
+        .collect()
+}
+pub fn signature_path(archive_file: &str) -> String {
+    format!("{}.sig", archive_file)
+}
+fn signed_message(archive_file: &str) -> Result<Vec<u8>, io::Error> {
+    // Only the named file is hashed, so the other volumes of a split archive would go unchecked.
+    if is_first_volume(archive_file) {
+        return Err(split_archive_error());
+    }
+    let mut reader = BufReader::new(File::open(archive_file)?);
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    message.extend_from_slice(&hasher.finalize());
+    Ok(message)
+}
+pub fn split_archive_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "multi-volume archives cannot be signed or verified",
+    )
+}
+pub fn generate_key(key_file: &str) -> Result<String, io::Error> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| io::Error::other("could not generate an Ed25519 key"))?;
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| io::Error::other("generated key is invalid"))?;
+
+    let public_key_file = format!("{}.pub", key_file);
+    // The private key is only ever readable by its owner, and existing keys are never
+    // overwritten.
+    let mut private_key = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(key_file)?;
+    let mut public_key = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&public_key_file)
+    {
+        Ok(public_key) => public_key,
+        Err(e) => {
+            fs::remove_file(key_file)?;
+            return Err(e);
+        }
+    };
+    private_key.write_all((to_hex(pkcs8.as_ref()) + "\n").as_bytes())?;
+    public_key.write_all((to_hex(key_pair.public_key().as_ref()) + "\n").as_bytes())?;
+    Ok(public_key_file)
+}
+pub fn sign_archive(archive_file: &str, key_file: &str) -> Result<String, io::Error> {
+    let pkcs8 = from_hex(&fs::read_to_string(key_file)?)?;
+    let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not an Ed25519 private key", key_file),
+        )
+    })?;
+
+    let signature = key_pair.sign(&signed_message(archive_file)?);
+    let signature_file = signature_path(archive_file);
+    fs::write(&signature_file, to_hex(signature.as_ref()) + "\n")?;
+    Ok(signature_file)
+}
+pub fn verify_signature(archive_file: &str, public_key_file: &str) -> Result<(), io::Error> {
+    let message = signed_message(archive_file)?;
+    let public_key = from_hex(&fs::read_to_string(public_key_file)?)?;
+    let signature_file = signature_path(archive_file);
+    let signature = match fs::read_to_string(&signature_file) {
+        Ok(signature) => from_hex(&signature)?,
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("cannot read signature {}: {}", signature_file, e),
+            ))
+        }
+    };
+
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&message, &signature)
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "signature {} does not match {}",
+                    signature_file, archive_file
+                ),
+            )
+        })
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+
+/// Data of members that are not written where they were read but may still be needed later,
+/// such as the target of a hard link. It is spooled to an unlinked temporary file, so only
+/// names and offsets are held in memory.
+#[derive(Debug, Default)]
+pub struct SkippedFiles {
+    spool: Option<File>,
+    length: u64,
+    files: HashMap<String, (u64, u64)>,
+}
+/// An unlinked temporary file, gone as soon as it is closed.
+pub fn open_spool() -> Result<File, io::Error> {
+    let path = std::env::temp_dir().join(format!("tar-archiver-{}.spool", std::process::id()));
+    let spool = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    Ok(spool)
+}
+impl SkippedFiles {
+    /// Keeps everything `data` yields under `name` and returns its size.
+    pub fn insert(&mut self, name: String, data: &mut dyn Read) -> Result<u64, io::Error> {
+        let spool = match &mut self.spool {
+            Some(spool) => spool,
+            None => self.spool.insert(open_spool()?),
+        };
+        spool.seek(SeekFrom::Start(self.length))?;
+        let size = io::copy(data, spool)?;
+        self.files.insert(name, (self.length, size));
+        self.length += size;
+        Ok(size)
+    }
+    pub fn size(&self, name: &str) -> Option<u64> {
+        self.files.get(name).map(|&(_, size)| size)
+    }
+    /// Writes the data kept for `name` to `output` and forgets it.
+    pub fn write_to(&mut self, name: &str, output: &mut dyn Write) -> Result<(), io::Error> {
+        if let (Some((offset, size)), Some(spool)) = (self.files.remove(name), &mut self.spool) {
+            spool.seek(SeekFrom::Start(offset))?;
+            io::copy(&mut spool.take(size), output)?;
+        }
+        Ok(())
+    }
+}
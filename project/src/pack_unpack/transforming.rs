@@ -0,0 +1,459 @@
+use crate::pack_unpack::entry::{Entry, EntryKind};
+use regex::{Captures, Regex, RegexBuilder};
+use std::io;
+
+/// Which of a member's names a rule is applied to, selected with the r, s and h flags.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NameKind {
+    Regular,
+    SymlinkTarget,
+    HardLinkTarget,
+}
+#[derive(Clone, Debug, PartialEq)]
+enum ReplacementPart {
+    Literal(String),
+    Group(usize),
+}
+#[derive(Clone, Debug)]
+struct TransformRule {
+    regex: Regex,
+    replacement: Vec<ReplacementPart>,
+    global: bool,
+    occurrence: usize,
+    kinds: Vec<NameKind>,
+}
+/// The --transform rules in the order they were given; every rule is applied to the result
+/// of the previous one, like a chain of sed commands.
+#[derive(Clone, Debug, Default)]
+pub struct Transforms {
+    rules: Vec<TransformRule>,
+}
+fn invalid_expression(expression: &str, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid transform '{}': {}", expression, message),
+    )
+}
+/// Splits the `s<d>regex<d>replacement<d>flags` command at the start of `expressions` at
+/// unescaped delimiters; a backslash before the delimiter makes it literal and is dropped.
+/// The command ends at the first `;` after its flags, and whatever follows is returned too.
+fn split_expression(expressions: &str) -> Result<(&str, [String; 3], &str), io::Error> {
+    let mut chars = expressions.char_indices();
+    if chars.next().map(|(_, c)| c) != Some('s') {
+        return Err(invalid_expression(
+            expressions,
+            "only s commands are supported",
+        ));
+    }
+    let delimiter = chars
+        .next()
+        .map(|(_, c)| c)
+        .ok_or_else(|| invalid_expression(expressions, "missing delimiter"))?;
+    let mut fields = vec![String::new()];
+    while let Some((position, c)) = chars.next() {
+        if fields.len() == 3 {
+            if c == ';' {
+                let (expression, rest) = expressions.split_at(position);
+                let fields = <[String; 3]>::try_from(fields).unwrap();
+                return Ok((expression, fields, &rest[1..]));
+            }
+            fields[2].push(c);
+        } else if c == '\\' {
+            match chars.next().map(|(_, c)| c) {
+                Some(next) if next == delimiter => fields.last_mut().unwrap().push(next),
+                Some(next) => {
+                    let field = fields.last_mut().unwrap();
+                    field.push('\\');
+                    field.push(next);
+                }
+                None => return Err(invalid_expression(expressions, "trailing backslash")),
+            }
+        } else if c == delimiter {
+            fields.push(String::new());
+        } else {
+            fields.last_mut().unwrap().push(c);
+        }
+    }
+    match <[String; 3]>::try_from(fields) {
+        Ok(fields) => Ok((expressions, fields, "")),
+        Err(_) => Err(invalid_expression(expressions, "unterminated s command")),
+    }
+}
+/// Copies the bracket expression starting at `chars[start]` (just past its `[`), where a
+/// backslash is an ordinary character, and returns the index after its closing `]`.
+fn translate_bracket(chars: &[char], start: usize, output: &mut String) -> Option<usize> {
+    let mut i = start;
+    output.push('[');
+    if chars.get(i) == Some(&'^') {
+        output.push('^');
+        i += 1;
+    }
+    if chars.get(i) == Some(&']') {
+        output.push_str("\\]");
+        i += 1;
+    }
+    while let Some(&c) = chars.get(i) {
+        match c {
+            ']' => {
+                output.push(']');
+                return Some(i + 1);
+            }
+            '[' if matches!(chars.get(i + 1), Some(':' | '=' | '.')) => {
+                let close = chars.get(i + 1).copied()?;
+                let end = (i + 2..chars.len().saturating_sub(1))
+                    .find(|&end| chars[end] == close && chars[end + 1] == ']')?;
+                output.extend(&chars[i..end + 2]);
+                i = end + 2;
+                continue;
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => output.push_str("\\-"),
+            '\\' | '[' | '&' | '~' => {
+                output.push('\\');
+                output.push(c);
+            }
+            c => output.push(c),
+        }
+        i += 1;
+    }
+    None
+}
+/// Rewrites a POSIX basic regular expression, the default for sed and GNU tar, in the
+/// syntax of the regex crate: `\(`, `\{`, `\|`, `\+` and `\?` become operators and their bare
+/// forms literals, and `*` or `^` that cannot be operators there are escaped.
+fn translate_basic(pattern: &str) -> Result<String, String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut output = String::new();
+    // Where a `*` is literal: at the start of the pattern, of a group or of an alternative.
+    let mut at_start = true;
+    let mut i = 0;
+    while let Some(&c) = chars.get(i) {
+        let was_at_start = std::mem::replace(&mut at_start, false);
+        match c {
+            '\\' => {
+                i += 1;
+                match chars.get(i) {
+                    Some(&next @ ('(' | '|')) => {
+                        output.push(next);
+                        at_start = true;
+                    }
+                    Some(&next @ (')' | '{' | '}' | '+' | '?')) => output.push(next),
+                    Some('1'..='9') => {
+                        return Err("back-references in the pattern are not supported".to_string())
+                    }
+                    Some('`') => output.push_str("\\A"),
+                    Some('\'') => output.push_str("\\z"),
+                    Some(&next @ ('n' | 't' | 'w' | 'W' | 's' | 'S' | 'b' | 'B' | '<' | '>')) => {
+                        output.push('\\');
+                        output.push(next);
+                    }
+                    Some(&next) => output.push_str(&regex::escape(&next.to_string())),
+                    None => return Err("trailing backslash".to_string()),
+                }
+            }
+            '*' if was_at_start => output.push_str("\\*"),
+            '^' if was_at_start => {
+                output.push('^');
+                at_start = true;
+            }
+            '$' if i + 1 == chars.len()
+                || matches!(chars.get(i + 1..i + 3), Some(['\\', ')' | '|'])) =>
+            {
+                output.push('$')
+            }
+            '^' | '$' | '(' | ')' | '{' | '}' | '|' | '+' | '?' => {
+                output.push('\\');
+                output.push(c);
+            }
+            '[' => {
+                i = translate_bracket(&chars, i + 1, &mut output)
+                    .ok_or_else(|| "unterminated bracket expression".to_string())?;
+                continue;
+            }
+            c => output.push(c),
+        }
+        i += 1;
+    }
+    Ok(output)
+}
+fn parse_replacement(replacement: &str) -> Vec<ReplacementPart> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        let group = match c {
+            '&' => Some(0),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => Some(digit as usize - '0' as usize),
+                Some('n') => {
+                    literal.push('\n');
+                    None
+                }
+                Some(other) => {
+                    literal.push(other);
+                    None
+                }
+                None => {
+                    literal.push('\\');
+                    None
+                }
+            },
+            c => {
+                literal.push(c);
+                None
+            }
+        };
+        if let Some(group) = group {
+            if !literal.is_empty() {
+                parts.push(ReplacementPart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(ReplacementPart::Group(group));
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(ReplacementPart::Literal(literal));
+    }
+    parts
+}
+impl TransformRule {
+    /// Parses the first command of `expressions` and returns the text after it.
+    fn parse(expressions: &str) -> Result<(Self, &str), io::Error> {
+        let (expression, [pattern, replacement, flags], rest) = split_expression(expressions)?;
+        let mut global = false;
+        let mut case_insensitive = false;
+        let mut extended = false;
+        let mut occurrence = String::new();
+        let mut selected = Vec::new();
+        let mut excluded = Vec::new();
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                'x' => extended = true,
+                '0'..='9' => occurrence.push(flag),
+                'r' | 's' | 'h' | 'R' | 'S' | 'H' => {
+                    let kind = match flag.to_ascii_lowercase() {
+                        'r' => NameKind::Regular,
+                        's' => NameKind::SymlinkTarget,
+                        _ => NameKind::HardLinkTarget,
+                    };
+                    if flag.is_ascii_lowercase() {
+                        selected.push(kind);
+                    } else {
+                        excluded.push(kind);
+                    }
+                }
+                _ => {
+                    return Err(invalid_expression(
+                        expression,
+                        &format!("unknown flag '{}'", flag),
+                    ))
+                }
+            }
+        }
+        // Lowercase selectors limit a rule to those names, uppercase ones leave names out.
+        let mut kinds = if selected.is_empty() {
+            vec![
+                NameKind::Regular,
+                NameKind::SymlinkTarget,
+                NameKind::HardLinkTarget,
+            ]
+        } else {
+            selected
+        };
+        kinds.retain(|kind| !excluded.contains(kind));
+        let occurrence = match occurrence.as_str() {
+            "" => 1,
+            digits => match digits.parse() {
+                Ok(0) | Err(_) => {
+                    return Err(invalid_expression(expression, "bad occurrence number"))
+                }
+                Ok(number) => number,
+            },
+        };
+        let pattern = if extended {
+            pattern
+        } else {
+            translate_basic(&pattern).map_err(|message| invalid_expression(expression, &message))?
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| invalid_expression(expression, &e.to_string()))?;
+        let replacement = parse_replacement(&replacement);
+        if let Some(ReplacementPart::Group(group)) = replacement.iter().find(
+            |part| matches!(part, ReplacementPart::Group(group) if *group >= regex.captures_len()),
+        ) {
+            return Err(invalid_expression(
+                expression,
+                &format!("reference \\{} to a missing group", group),
+            ));
+        }
+        let rule = TransformRule {
+            regex,
+            replacement,
+            global,
+            occurrence,
+            kinds,
+        };
+        Ok((rule, rest))
+    }
+    fn expand(&self, captures: &Captures, output: &mut String) {
+        for part in &self.replacement {
+            match part {
+                ReplacementPart::Literal(literal) => output.push_str(literal),
+                ReplacementPart::Group(group) => {
+                    output.push_str(captures.get(*group).map_or("", |group| group.as_str()))
+                }
+            }
+        }
+    }
+    /// Replaces the chosen occurrence, or with g that one and every later one, as sed does.
+    fn apply(&self, name: &str) -> String {
+        let mut output = String::new();
+        let mut last_end = 0;
+        for (number, captures) in self.regex.captures_iter(name).enumerate() {
+            let number = number + 1;
+            if number < self.occurrence {
+                continue;
+            }
+            let matched = captures.get(0).unwrap();
+            output.push_str(&name[last_end..matched.start()]);
+            self.expand(&captures, &mut output);
+            last_end = matched.end();
+            if !self.global {
+                break;
+            }
+        }
+        output.push_str(&name[last_end..]);
+        output
+    }
+}
+impl Transforms {
+    /// Adds the rules of one --transform argument, which may hold several expressions
+    /// separated by `;`. A `;` inside the regex or the replacement belongs to them.
+    pub fn add(&mut self, expressions: &str) -> Result<(), io::Error> {
+        let mut rest = expressions.trim_start_matches(';');
+        while !rest.is_empty() {
+            let (rule, next) = TransformRule::parse(rest)?;
+            self.rules.push(rule);
+            rest = next.trim_start_matches(';');
+        }
+        Ok(())
+    }
+    pub fn apply(&self, name: &str, kind: NameKind) -> String {
+        self.rules
+            .iter()
+            .filter(|rule| rule.kinds.contains(&kind))
+            .fold(name.to_string(), |name, rule| rule.apply(&name))
+    }
+    pub fn apply_to_entry(&self, entry: &mut Entry) {
+        entry.name = self.apply(&entry.name, NameKind::Regular);
+        match entry.kind {
+            EntryKind::Symlink => {
+                entry.link_name = self.apply(&entry.link_name, NameKind::SymlinkTarget)
+            }
+            EntryKind::HardLink => {
+                entry.link_name = self.apply(&entry.link_name, NameKind::HardLinkTarget)
+            }
+            _ => {}
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transforms(expressions: &[&str]) -> Transforms {
+        let mut transforms = Transforms::default();
+        for expression in expressions {
+            transforms.add(expression).unwrap();
+        }
+        transforms
+    }
+    fn apply(expressions: &[&str], name: &str) -> String {
+        transforms(expressions).apply(name, NameKind::Regular)
+    }
+
+    #[test]
+    fn later_rules_see_earlier_output() {
+        assert_eq!(
+            apply(&["s,^usr/,opt/,", "s,^opt/,srv/,"], "usr/bin"),
+            "srv/bin"
+        );
+        assert_eq!(apply(&["s,a,b,;s,b,c,"], "a"), "c");
+    }
+    #[test]
+    fn semicolons_inside_a_command_are_not_separators() {
+        assert_eq!(apply(&["s,a;b,c,"], "xa;by"), "xcy");
+        assert_eq!(apply(&["s,a,b;c,"], "a"), "b;c");
+        assert_eq!(apply(&["s,a;b,c,g;s,c,d,"], "a;ba;b"), "dc");
+        assert!(Transforms::default().add("s,a;b,c").is_err());
+    }
+    #[test]
+    fn global_case_and_occurrence_flags() {
+        assert_eq!(apply(&["s,o,0,"], "foo/boo"), "f0o/boo");
+        assert_eq!(apply(&["s,o,0,g"], "foo/boo"), "f00/b00");
+        assert_eq!(apply(&["s,O,0,i"], "foo"), "f0o");
+        assert_eq!(apply(&["s,O,0,"], "foo"), "foo");
+        assert_eq!(apply(&["s,o,0,3"], "foo/boo"), "foo/b0o");
+        assert_eq!(apply(&["s,o,0,2g"], "foo/boo"), "fo0/b00");
+        assert!(Transforms::default().add("s,o,0,0").is_err());
+    }
+    #[test]
+    fn name_kind_selectors() {
+        let only_symlinks = transforms(&["s,a,b,s"]);
+        assert_eq!(only_symlinks.apply("a", NameKind::Regular), "a");
+        assert_eq!(only_symlinks.apply("a", NameKind::SymlinkTarget), "b");
+        assert_eq!(only_symlinks.apply("a", NameKind::HardLinkTarget), "a");
+
+        let regular_and_hard_links = transforms(&["s,a,b,rh"]);
+        assert_eq!(regular_and_hard_links.apply("a", NameKind::Regular), "b");
+        assert_eq!(
+            regular_and_hard_links.apply("a", NameKind::SymlinkTarget),
+            "a"
+        );
+        assert_eq!(
+            regular_and_hard_links.apply("a", NameKind::HardLinkTarget),
+            "b"
+        );
+
+        let no_symlinks = transforms(&["s,a,b,S"]);
+        assert_eq!(no_symlinks.apply("a", NameKind::Regular), "b");
+        assert_eq!(no_symlinks.apply("a", NameKind::SymlinkTarget), "a");
+        assert_eq!(no_symlinks.apply("a", NameKind::HardLinkTarget), "b");
+
+        let only_links = transforms(&["s,a,b,RSH;s,a,c,R"]);
+        assert_eq!(only_links.apply("a", NameKind::Regular), "a");
+        assert_eq!(only_links.apply("a", NameKind::SymlinkTarget), "c");
+        assert_eq!(only_links.apply("a", NameKind::HardLinkTarget), "c");
+    }
+    #[test]
+    fn group_references_in_the_replacement() {
+        assert_eq!(apply(&[r"s,\(.*\)/\(.*\),\2/\1,"], "a/b"), "b/a");
+        assert_eq!(apply(&[r"s,(.*)/(.*),\2/\1,x"], "a/b"), "b/a");
+        assert_eq!(apply(&["s,[a-z]*,<&>,"], "abc/d"), "<abc>/d");
+        assert_eq!(apply(&[r"s,b,\&,"], "abc"), "a&c");
+        assert!(Transforms::default().add(r"s,a,\1,").is_err());
+    }
+    #[test]
+    fn patterns_are_basic_unless_extended() {
+        assert_eq!(apply(&["s,(a),b,"], "(a)"), "b");
+        assert_eq!(apply(&["s,a+,b,"], "aa+"), "ab");
+        assert_eq!(apply(&[r"s,a\+,b,"], "aa+"), "b+");
+        assert_eq!(apply(&["s,a+,b,x"], "aa+"), "b+");
+        assert_eq!(apply(&[r"s,a\{2\},b,"], "aaa"), "ba");
+        assert_eq!(apply(&[r"s,x\|y,z,g"], "xy|"), "zz|");
+        assert_eq!(apply(&["s,*a,b,"], "*a"), "b");
+        assert_eq!(apply(&["s,a^b$c,d,"], "a^b$c"), "d");
+        assert_eq!(apply(&[r"s,[\.]x,y,"], "\\x.x"), "y.x");
+        assert_eq!(apply(&[r"s,[[:digit:]]\{1\,\},N,g"], "a12b3"), "aNbN");
+        assert!(Transforms::default().add(r"s,\(a\)\1,b,").is_err());
+        assert!(Transforms::default().add("s,[a,b,").is_err());
+    }
+    #[test]
+    fn escaped_delimiters() {
+        assert_eq!(apply(&[r"s/usr\/lib/lib/"], "usr/lib/x"), "lib/x");
+        assert_eq!(apply(&[r"s/lib/usr\/lib/"], "lib/x"), "usr/lib/x");
+        assert_eq!(apply(&[r"s,a\,b,c,"], "a,b"), "c");
+    }
+}
@@ -1,11 +1,33 @@
-use flate2::read::GzDecoder;
+use crate::pack_unpack::cpio::{is_cpio, read_cpio_entries};
+use crate::pack_unpack::digests::{
+    parse_manifest, parse_pax_records, report_manifest_mismatches, sha256_hex, DIGEST_KEY,
+    MANIFEST_NAME,
+};
+use crate::pack_unpack::encryption::{is_encrypted, read_passphrase, DecryptingReader};
+use crate::pack_unpack::entry::{Entry, EntryKind};
+use crate::pack_unpack::incremental::apply_dumpdir;
+use crate::pack_unpack::indexing::{is_seekable_archive, open_member, read_index, IndexEntry};
+use crate::pack_unpack::layers::{
+    clear_directory, parse_whiteout, prepare_layer_path, remove_path, Whiteout,
+};
+use crate::pack_unpack::patterns::member_matches;
+use crate::pack_unpack::signing::{split_archive_error, verify_signature};
+use crate::pack_unpack::spooling::SkippedFiles;
+use crate::pack_unpack::transforming::{NameKind, Transforms};
+use crate::pack_unpack::users::IdResolver;
+use crate::pack_unpack::volumes::{is_first_volume, MultiVolumeReader};
+use crate::pack_unpack::zip::{copy_member_data, is_zip, read_central_directory, read_member_data};
+use flate2::read::MultiGzDecoder;
 use nix::libc::dev_t;
 use nix::sys::stat::{mknod, Mode};
-use nix::unistd::mkfifo;
+use nix::unistd::{mkfifo, Uid};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
 use std::{fs, io};
+use xz2::read::XzDecoder;
 
 #[derive(Debug)]
 pub struct UStarHeader {
@@ -49,7 +71,7 @@ impl UStarHeader {
             padding: [0; 12],
         }
     }
-    fn from_bytes(bytes: &[u8]) -> Self {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
         let mut header = UStarHeader::new();
 
         header.name.copy_from_slice(&bytes[0..100]);
@@ -72,7 +94,30 @@ impl UStarHeader {
 
         header
     }
-    fn file_name(&self) -> String {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(512);
+
+        bytes.extend_from_slice(&self.name);
+        bytes.extend_from_slice(&self.mode);
+        bytes.extend_from_slice(&self.uid);
+        bytes.extend_from_slice(&self.gid);
+        bytes.extend_from_slice(&self.size);
+        bytes.extend_from_slice(&self.modification_time);
+        bytes.extend_from_slice(&self.checksum);
+        bytes.push(self.type_flag[0]);
+        bytes.extend_from_slice(&self.link_name);
+        bytes.extend_from_slice(&self.ustar);
+        bytes.extend_from_slice(&self.version);
+        bytes.extend_from_slice(&self.user_name);
+        bytes.extend_from_slice(&self.group_name);
+        bytes.extend_from_slice(&self.device_major);
+        bytes.extend_from_slice(&self.device_minor);
+        bytes.extend_from_slice(&self.prefix);
+        bytes.extend_from_slice(&self.padding);
+
+        bytes
+    }
+    pub fn file_name(&self) -> String {
         let mut name = String::from_utf8(self.name.to_vec())
             .unwrap()
             .trim_end_matches('\0')
@@ -86,7 +131,74 @@ impl UStarHeader {
         }
         name
     }
-    fn file_size(&self) -> usize {
+    pub fn link_name(&self) -> String {
+        String::from_utf8_lossy(&self.link_name)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+    pub fn user_name(&self) -> String {
+        String::from_utf8_lossy(&self.user_name)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+    pub fn group_name(&self) -> String {
+        String::from_utf8_lossy(&self.group_name)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+    pub fn type_flag(&self) -> char {
+        self.type_flag[0] as char
+    }
+    pub fn data_size(&self) -> usize {
+        match self.type_flag() {
+            '1' | '2' | '3' | '4' | '5' | '6' => 0,
+            _ => self.file_size(),
+        }
+    }
+    pub fn set_link_name(&mut self, link_name: &str) {
+        self.link_name.fill(0);
+        self.link_name[..link_name.len()].copy_from_slice(link_name.as_bytes());
+    }
+    pub fn make_regular_file(&mut self, size: usize) {
+        self.type_flag[0] = b'0';
+        self.link_name.fill(0);
+        self.size.fill(0);
+        let file_size = format!("{:0>11o}\0", size);
+        self.size[..file_size.len()].copy_from_slice(file_size.as_bytes());
+    }
+    pub fn update_checksum(&mut self) {
+        self.checksum.fill(b' ');
+        let checksum: u32 = self.as_bytes().iter().map(|&b| b as u32).sum();
+        let checksum_str = format!("{:06o}\0", checksum);
+        self.checksum[..checksum_str.len()].copy_from_slice(checksum_str.as_bytes());
+    }
+    pub fn mode_bits(&self) -> u32 {
+        octal_value(&self.mode) as u32
+    }
+    pub fn uid(&self) -> u32 {
+        octal_value(&self.uid) as u32
+    }
+    pub fn gid(&self) -> u32 {
+        octal_value(&self.gid) as u32
+    }
+    pub fn modification_time(&self) -> i64 {
+        octal_value(&self.modification_time) as i64
+    }
+    pub fn device_numbers(&self) -> (u64, u64) {
+        (
+            octal_value(&self.device_major),
+            octal_value(&self.device_minor),
+        )
+    }
+    pub fn checksum_is_valid(&self) -> bool {
+        let stored = octal_value(&self.checksum) as u32;
+        let mut bytes = self.as_bytes();
+        bytes[148..156].fill(b' ');
+        let unsigned: u32 = bytes.iter().map(|&b| b as u32).sum();
+        let signed: i32 = bytes.iter().map(|&b| b as i8 as i32).sum();
+        stored == unsigned || stored as i32 == signed
+    }
+    pub fn file_size(&self) -> usize {
         usize::from_str_radix(
             std::str::from_utf8(&self.size)
                 .unwrap_or("0")
@@ -96,131 +208,906 @@ impl UStarHeader {
         .unwrap_or(0)
     }
     fn get_mode(&self) -> Mode {
-        let mode_str = String::from_utf8_lossy(&self.mode)
-            .trim_end_matches('\0')
-            .to_string();
-
-        u32::from_str_radix(&mode_str, 8)
-            .map(Mode::from_bits_truncate)
-            .unwrap()
+        // Space-terminated modes written by other tools must not abort the extraction.
+        Mode::from_bits_truncate(self.mode_bits())
     }
 }
-pub fn extract_files(tar_file: &str, is_compressed: bool) -> Result<(), io::Error> {
-    let file = File::open(tar_file)?;
-
-    let mut reader: Box<dyn Read> = if is_compressed {
-        Box::new(GzDecoder::new(file))
+fn octal_value(field: &[u8]) -> u64 {
+    let digits: String = field
+        .iter()
+        .map(|&b| b as char)
+        .skip_while(|c| *c == ' ')
+        .take_while(|c| c.is_digit(8))
+        .collect();
+    u64::from_str_radix(&digits, 8).unwrap_or(0)
+}
+pub fn is_gzip(tar_file: &str) -> Result<bool, io::Error> {
+    let mut magic = [0u8; 2];
+    let mut file = File::open(tar_file)?;
+    let read = file.read(&mut magic)?;
+    Ok(read == 2 && magic == [0x1f, 0x8b])
+}
+pub fn read_block(reader: &mut dyn Read, buffer: &mut [u8]) -> Result<bool, io::Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if filled == 0 {
+        Ok(false)
+    } else if filled < buffer.len() {
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "archive is truncated inside a header block",
+        ))
     } else {
-        Box::new(file)
-    };
+        Ok(true)
+    }
+}
+pub fn truncated_member(file_name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("archive is truncated inside member '{}'", file_name),
+    )
+}
+/// Reports running out of data as a truncated member and passes any other read error through.
+pub fn or_truncated(error: io::Error, file_name: &str) -> io::Error {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+        truncated_member(file_name)
+    } else {
+        error
+    }
+}
+fn check_signature(tar_file: &str, options: &UnpackOptions) -> Result<(), io::Error> {
+    if let Some(public_key_file) = &options.signature_key {
+        if !options.volumes.is_empty() {
+            return Err(split_archive_error());
+        }
+        verify_signature(tar_file, public_key_file)?;
+    }
+    Ok(())
+}
+pub fn test_archive(tar_file: &str, options: &UnpackOptions) -> Result<usize, io::Error> {
+    check_signature(tar_file, options)?;
+    let mut reader = open_archive_volumes(tar_file, &options.volumes)?;
     let mut buffer = vec![0; 512];
+    let mut members = 0;
+    let mut problems = 0;
 
-    while reader.read_exact(&mut buffer).is_ok() {
+    loop {
+        if !read_block(&mut reader, &mut buffer)? {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "end-of-archive marker is missing after {} member(s), the archive is truncated",
+                    members
+                ),
+            ));
+        }
         if buffer.iter().all(|&b| b == 0) {
+            if !read_block(&mut reader, &mut buffer)? || buffer.iter().any(|&b| b != 0) {
+                println!("Warning: end-of-archive marker has only one zero block");
+                problems += 1;
+            }
             break;
         }
+
         let header = UStarHeader::from_bytes(&buffer);
         let file_name = header.file_name();
-        let file_size = header.file_size();
-        let type_flag = header.type_flag[0] as char;
-        let mode = header.get_mode();
-        let major = usize::from_str_radix(
-            std::str::from_utf8(&header.device_major)
+        if !header.checksum_is_valid() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "header checksum mismatch for '{}' after {} member(s)",
+                    file_name, members
+                ),
+            ));
+        }
+
+        let size = header.data_size();
+        let padding = (512 - (size % 512)) % 512;
+        let copied = io::copy(&mut (&mut reader).take(size as u64), &mut io::sink())?;
+        if copied < size as u64 {
+            return Err(truncated_member(&file_name));
+        }
+        let mut padding_bytes = vec![0; padding];
+        reader
+            .read_exact(&mut padding_bytes)
+            .map_err(|e| or_truncated(e, &file_name))?;
+        if padding_bytes.iter().any(|&b| b != 0) {
+            println!("Warning: padding after '{}' is not zero-filled", file_name);
+            problems += 1;
+        }
+        members += 1;
+    }
+
+    // Draining the rest of the stream makes the decompressor check its trailer.
+    io::copy(&mut reader, &mut io::sink())?;
+    if problems > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} problem(s) found in {} member(s)", problems, members),
+        ));
+    }
+    Ok(members)
+}
+pub fn is_encrypted_archive(tar_file: &str) -> Result<bool, io::Error> {
+    let mut file = BufReader::new(File::open(tar_file)?);
+    Ok(is_encrypted(file.fill_buf()?))
+}
+pub fn open_archive(tar_file: &str) -> Result<Box<dyn Read>, io::Error> {
+    open_archive_volumes(tar_file, &[])
+}
+pub fn open_archive_volumes(
+    tar_file: &str,
+    volumes: &[String],
+) -> Result<Box<dyn Read>, io::Error> {
+    let mut file: Box<dyn BufRead> = if !volumes.is_empty() || is_first_volume(tar_file) {
+        Box::new(BufReader::new(MultiVolumeReader::open(tar_file, volumes)?))
+    } else {
+        Box::new(BufReader::new(File::open(tar_file)?))
+    };
+    let reader: Box<dyn Read> = if is_encrypted(file.fill_buf()?) {
+        Box::new(DecryptingReader::new(file, &read_passphrase()?)?)
+    } else {
+        Box::new(file)
+    };
+
+    decompress(reader)
+}
+pub fn decompress<'a>(reader: Box<dyn Read + 'a>) -> Result<Box<dyn Read + 'a>, io::Error> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Ok(Box::new(XzDecoder::new_multi_decoder(reader)))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+#[derive(Debug, Default)]
+pub struct UnpackOptions {
+    pub signature_key: Option<String>,
+    pub incremental: bool,
+    pub volumes: Vec<String>,
+    pub members: Vec<String>,
+    pub destination: Option<String>,
+    pub layer: bool,
+    pub numeric_owner: bool,
+    pub transforms: Transforms,
+}
+impl UnpackOptions {
+    /// The directory members are extracted under: the destination, or the working directory.
+    fn root(&self) -> &Path {
+        Path::new(self.destination.as_deref().unwrap_or("."))
+    }
+    /// Where a member is written, always under the extraction root. Names with `..` are
+    /// refused, and no symlink extracted earlier (say `etc -> /`) may carry a member out of it.
+    fn output_path(&self, file_name: &str) -> Result<PathBuf, io::Error> {
+        self.checked_path(file_name, false)
+    }
+    /// Like `output_path`, for a directory that is followed once created: a symlink already
+    /// standing at the path itself must stay inside the root too.
+    fn directory_path(&self, file_name: &str) -> Result<PathBuf, io::Error> {
+        self.checked_path(file_name, true)
+    }
+    fn checked_path(&self, file_name: &str, check_last: bool) -> Result<PathBuf, io::Error> {
+        let relative = Path::new(file_name.trim_start_matches('/'));
+        if relative
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("refusing to extract '{}': it contains '..'", file_name),
+            ));
+        }
+        let root = self.root();
+        // Rebuilt from components so a trailing '/' cannot make a symlink look like its target.
+        let path = root.join(relative.components().collect::<PathBuf>());
+        let parents: Vec<&Path> = path
+            .ancestors()
+            .skip(if check_last { 0 } else { 1 })
+            .take_while(|parent| *parent != root)
+            .filter(|parent| parent.is_symlink())
+            .collect();
+        if !parents.is_empty() {
+            let resolved_root = root.canonicalize()?;
+            for parent in parents {
+                if !parent
+                    .canonicalize()
+                    .is_ok_and(|resolved| resolved.starts_with(&resolved_root))
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "refusing to extract '{}': {} leads outside {}",
+                            file_name,
+                            parent.display(),
+                            root.display()
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(path)
+    }
+}
+/// Creates a regular file, replacing rather than following a symlink already at the path.
+fn create_file(path: &Path) -> Result<File, io::Error> {
+    if path.is_symlink() {
+        fs::remove_file(path)?;
+    }
+    File::create(path)
+}
+#[derive(Debug, Default)]
+struct ExtractState {
+    pax_records: HashMap<String, String>,
+    computed: HashMap<String, String>,
+    manifest: Option<HashMap<String, String>>,
+    mismatches: usize,
+    layer_paths: HashSet<PathBuf>,
+    resolver: IdResolver,
+    hard_links: HardLinks,
+    // Unrequested files that selected hard links point to; without a pre-scan all are kept.
+    link_targets: Option<HashSet<String>>,
+}
+#[derive(Debug)]
+struct PendingLink {
+    target_name: String,
+    target: PathBuf,
+    path: PathBuf,
+}
+/// Hard links are made against the extraction root. When member filters left out a link's
+/// target, the target's data is kept and the first selected link becomes a regular file that
+/// the later links point to; links that come before their target wait until the end.
+#[derive(Debug, Default)]
+struct HardLinks {
+    skipped_files: SkippedFiles,
+    promoted: HashMap<String, PathBuf>,
+    pending: Vec<PendingLink>,
+}
+/// Links `path` to `target`, copying the file where a link cannot be made (e.g. across
+/// file systems or on file systems without hard links).
+fn link_or_copy(target: &Path, path: &Path) -> Result<(), io::Error> {
+    if path == target {
+        return Ok(());
+    }
+    if path
+        .symlink_metadata()
+        .is_ok_and(|metadata| !metadata.is_dir())
+    {
+        fs::remove_file(path)?;
+    }
+    if let Err(e) = fs::hard_link(target, path) {
+        fs::copy(target, path).map_err(|_| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "{}: cannot link or copy {}: {}",
+                    path.display(),
+                    target.display(),
+                    e
+                ),
+            )
+        })?;
+    }
+    Ok(())
+}
+impl HardLinks {
+    fn extract(&mut self, target_name: &str, target: &Path, path: &Path) -> Result<(), io::Error> {
+        if self.skipped_files.size(target_name).is_some() {
+            if path
+                .symlink_metadata()
+                .is_ok_and(|metadata| !metadata.is_dir())
+            {
+                fs::remove_file(path)?;
+            }
+            self.skipped_files
+                .write_to(target_name, &mut File::create(path)?)?;
+            self.promoted
+                .insert(target_name.to_string(), path.to_path_buf());
+        } else if let Some(promoted) = self.promoted.get(target_name) {
+            link_or_copy(promoted, path)?;
+        } else if target.symlink_metadata().is_ok() {
+            link_or_copy(target, path)?;
+        } else {
+            self.pending.push(PendingLink {
+                target_name: target_name.to_string(),
+                target: target.to_path_buf(),
+                path: path.to_path_buf(),
+            });
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), io::Error> {
+        for link in std::mem::take(&mut self.pending) {
+            if link.target.symlink_metadata().is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "{}: hard link target {} is not in the archive",
+                        link.path.display(),
+                        link.target_name
+                    ),
+                ));
+            }
+            link_or_copy(&link.target, &link.path)?;
+        }
+        Ok(())
+    }
+}
+/// Gives an extracted member its archived owner. Only root may do that, everyone else keeps
+/// owning what they extract.
+fn restore_owner(
+    path: &Path,
+    user_name: &str,
+    group_name: &str,
+    ids: (u32, u32),
+    resolver: &mut IdResolver,
+    options: &UnpackOptions,
+) -> Result<(), io::Error> {
+    if !Uid::effective().is_root() {
+        return Ok(());
+    }
+    let (uid, gid) = resolver.owner_ids(user_name, group_name, ids, options.numeric_owner);
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+}
+fn skip_member_data(reader: &mut dyn Read, header: &UStarHeader) -> Result<(), io::Error> {
+    let data_size = header.data_size();
+    io::copy(
+        &mut reader.take((data_size + (512 - (data_size % 512)) % 512) as u64),
+        &mut io::sink(),
+    )?;
+    Ok(())
+}
+fn is_requested(options: &UnpackOptions, file_name: &str) -> bool {
+    options.members.is_empty()
+        || options
+            .members
+            .iter()
+            .any(|member| member_matches(member, file_name))
+}
+fn extract_member(
+    reader: &mut dyn Read,
+    buffer: &[u8],
+    state: &mut ExtractState,
+    options: &UnpackOptions,
+) -> Result<bool, io::Error> {
+    let header = UStarHeader::from_bytes(buffer);
+    let file_name = header.file_name();
+    if !header.checksum_is_valid() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("header checksum mismatch for '{}'", file_name),
+        ));
+    }
+    let file_size = header.file_size();
+    let type_flag = header.type_flag[0] as char;
+    if type_flag == 'x' || type_flag == 'g' {
+        let mut content = vec![0; file_size];
+        reader
+            .read_exact(&mut content)
+            .map_err(|e| or_truncated(e, &file_name))?;
+        reader
+            .read_exact(&mut vec![0; (512 - (file_size % 512)) % 512])
+            .map_err(|e| or_truncated(e, &file_name))?;
+        if type_flag == 'x' {
+            state.pax_records = parse_pax_records(&content);
+        }
+        return Ok(false);
+    }
+    let file_name = state.pax_records.remove("path").unwrap_or(file_name);
+    let expected_digest = state.pax_records.remove(DIGEST_KEY);
+    let user_name = state
+        .pax_records
+        .remove("uname")
+        .unwrap_or_else(|| header.user_name());
+    let group_name = state
+        .pax_records
+        .remove("gname")
+        .unwrap_or_else(|| header.group_name());
+    let ids = (
+        state
+            .pax_records
+            .remove("uid")
+            .and_then(|uid| uid.parse().ok())
+            .unwrap_or_else(|| header.uid()),
+        state
+            .pax_records
+            .remove("gid")
+            .and_then(|gid| gid.parse().ok())
+            .unwrap_or_else(|| header.gid()),
+    );
+    state.pax_records.clear();
+    if !is_requested(options, &file_name) {
+        let is_link_target = state
+            .link_targets
+            .as_ref()
+            .is_none_or(|targets| targets.contains(&file_name));
+        if type_flag == '0' && is_link_target {
+            // Kept for a selected hard link that needs the data.
+            let kept = state
+                .hard_links
+                .skipped_files
+                .insert(file_name.clone(), &mut reader.take(file_size as u64))?;
+            if kept < file_size as u64 {
+                return Err(truncated_member(&file_name));
+            }
+            reader
+                .read_exact(&mut vec![0; (512 - (file_size % 512)) % 512])
+                .map_err(|e| or_truncated(e, &file_name))?;
+        } else {
+            skip_member_data(reader, &header)?;
+        }
+        return Ok(true);
+    }
+    // Members are selected by their archived names and written under the transformed ones.
+    let output_name = options.transforms.apply(&file_name, NameKind::Regular);
+    let path = if matches!(type_flag, '5' | 'D') {
+        options.directory_path(&output_name)?
+    } else {
+        options.output_path(&output_name)?
+    };
+    if options.layer {
+        match parse_whiteout(&file_name)? {
+            Some(whiteout) => {
+                skip_member_data(reader, &header)?;
+                match whiteout {
+                    Whiteout::Remove(hidden) => remove_path(
+                        &options
+                            .output_path(&options.transforms.apply(&hidden, NameKind::Regular))?,
+                    )?,
+                    Whiteout::Opaque(directory) => clear_directory(
+                        options.root(),
+                        &options.output_path(
+                            &options.transforms.apply(&directory, NameKind::Regular),
+                        )?,
+                        &state.layer_paths,
+                    )?,
+                }
+                return Ok(true);
+            }
+            None => {
+                prepare_layer_path(&path, matches!(type_flag, '5' | 'D'))?;
+                state.layer_paths.insert(path.clone());
+            }
+        }
+    }
+    let mode = header.get_mode();
+    let major = usize::from_str_radix(
+        std::str::from_utf8(&header.device_major)
+            .unwrap()
+            .trim_end_matches('\0')
+            .trim(),
+        8,
+    )
+    .unwrap_or(0);
+    let minor = usize::from_str_radix(
+        std::str::from_utf8(&header.device_minor)
+            .unwrap()
+            .trim_end_matches('\0')
+            .trim(),
+        8,
+    )
+    .unwrap_or(0);
+    match type_flag {
+        '0' => {
+            let mut content = vec![0; file_size];
+            reader
+                .read_exact(&mut content)
+                .map_err(|e| or_truncated(e, &file_name))?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut output_file = create_file(&path)?;
+            output_file.write_all(&content)?;
+
+            let digest = sha256_hex(&content);
+            if expected_digest.is_some_and(|expected| expected != digest) {
+                println!("{}: digest mismatch", file_name);
+                state.mismatches += 1;
+            }
+            if file_name == MANIFEST_NAME {
+                state.manifest = Some(parse_manifest(&content));
+            } else {
+                state.computed.insert(file_name.clone(), digest);
+            }
+
+            let padding = (512 - (file_size % 512)) % 512;
+            reader
+                .read_exact(&mut vec![0; padding])
+                .map_err(|e| or_truncated(e, &file_name))?;
+        }
+        '1' => {
+            let link_target = String::from_utf8(Vec::from(&header.link_name))
                 .unwrap()
                 .trim_end_matches('\0')
-                .trim(),
-            8,
-        )
-        .unwrap_or(0);
-        let minor = usize::from_str_radix(
-            std::str::from_utf8(&header.device_minor)
+                .to_string();
+            let target = options.output_path(
+                &options
+                    .transforms
+                    .apply(&link_target, NameKind::HardLinkTarget),
+            )?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            state.hard_links.extract(&link_target, &target, &path)?;
+        }
+        '2' => {
+            let link_target = String::from_utf8(Vec::from(&header.link_name))
                 .unwrap()
                 .trim_end_matches('\0')
-                .trim(),
-            8,
-        )
-        .unwrap_or(0);
-        match type_flag {
-            '0' => {
-                let mut content = vec![0; file_size];
-                reader.read_exact(&mut content)?;
-                if let Some(parent) = Path::new(&file_name).parent() {
-                    fs::create_dir_all(parent)?;
-                }
-                let mut output_file = File::create(&file_name)?;
-                output_file.write_all(&content)?;
-
-                let padding = (512 - (file_size % 512)) % 512;
-                reader.read_exact(&mut vec![0; padding])?;
-            }
-            '1' => {
-                let link_target = String::from_utf8(Vec::from(&header.link_name))
-                    .unwrap()
-                    .trim_end_matches('\0')
-                    .to_string();
-                fs::hard_link(link_target, &file_name)?;
-            }
-            '2' => {
-                let link_target = String::from_utf8(Vec::from(&header.link_name))
-                    .unwrap()
-                    .trim_end_matches('\0')
-                    .to_string();
-                std::os::unix::fs::symlink(&link_target, &file_name)?;
-            }
-            '3' => {
-                match mknod(
-                    Path::new(&file_name),
-                    nix::sys::stat::SFlag::S_IFCHR,
-                    mode,
-                    ((major << 8) | minor) as dev_t,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error:{e}.Run with sudo!\n\
-                                    Use cargo build --release \n\
-                                    Than execute sudo ./target/release/project ");
-                    }
+                .to_string();
+            let link_target = options
+                .transforms
+                .apply(&link_target, NameKind::SymlinkTarget);
+            std::os::unix::fs::symlink(&link_target, &path)?;
+        }
+        '3' => {
+            match mknod(
+                &path,
+                nix::sys::stat::SFlag::S_IFCHR,
+                mode,
+                ((major << 8) | minor) as dev_t,
+            ) {
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Error:{e}.Run with sudo!\n\
+                                Use cargo build --release \n\
+                                Than execute sudo ./target/release/project ");
                 }
             }
-            '4' => {
-                match mknod(
-                    Path::new(&file_name),
-                    nix::sys::stat::SFlag::S_IFBLK,
-                    mode,
-                    ((major << 8) | minor) as dev_t,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error:{e}.Run with sudo!\n\
-                                    Use cargo build --release \n\
-                                    Than execute sudo ./target/release/project ");
-                    }
+        }
+        '4' => {
+            match mknod(
+                &path,
+                nix::sys::stat::SFlag::S_IFBLK,
+                mode,
+                ((major << 8) | minor) as dev_t,
+            ) {
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Error:{e}.Run with sudo!\n\
+                                Use cargo build --release \n\
+                                Than execute sudo ./target/release/project ");
                 }
             }
-            '5' => {
-                if Path::new(&file_name).exists() {
-                    println!("Directory '{}' already exists.", file_name);
-                    println!("Do you want to overwrite it? (y/n): ");
-
-                    let mut response = String::new();
-                    io::stdin().read_line(&mut response)?;
-
-                    if response.trim().to_lowercase() == "y" {
-                        println!("Overwriting directory: {}", file_name);
-                        fs::remove_dir_all(&file_name)?;
-                        fs::create_dir(&file_name)?;
-                    } else {
-                        fs::create_dir(&file_name)?;
-                    }
+        }
+        '5' => {
+            if path.exists() && !options.layer {
+                println!("Directory '{}' already exists.", file_name);
+                println!("Do you want to overwrite it? (y/n): ");
+
+                let mut response = String::new();
+                io::stdin().read_line(&mut response)?;
+
+                if response.trim().to_lowercase() == "y" {
+                    println!("Overwriting directory: {}", file_name);
+                    fs::remove_dir_all(&path)?;
+                    fs::create_dir(&path)?;
                 }
+            } else {
+                fs::create_dir_all(&path)?;
+            }
+        }
+        'D' => {
+            let mut content = vec![0; file_size];
+            reader
+                .read_exact(&mut content)
+                .map_err(|e| or_truncated(e, &file_name))?;
+            reader
+                .read_exact(&mut vec![0; (512 - (file_size % 512)) % 512])
+                .map_err(|e| or_truncated(e, &file_name))?;
+            fs::create_dir_all(&path)?;
+            if options.incremental {
+                apply_dumpdir(options.root(), &path, &content)?;
             }
-            '6' => {
-                mkfifo(Path::new(&file_name), mode)?;
+        }
+        '6' => {
+            mkfifo(&path, mode)?;
+        }
+        _ => {
+            println!("Unknown type flag: {}", type_flag);
+            let padding = (512 - (file_size % 512)) % 512;
+            io::copy(
+                &mut reader.take((file_size + padding) as u64),
+                &mut io::sink(),
+            )?;
+            return Ok(true);
+        }
+    }
+    if path.symlink_metadata().is_ok() {
+        restore_owner(
+            &path,
+            &user_name,
+            &group_name,
+            ids,
+            &mut state.resolver,
+            options,
+        )?;
+    }
+    Ok(true)
+}
+fn finish_extraction(mut state: ExtractState) -> Result<(), io::Error> {
+    state.hard_links.finish()?;
+    let mut mismatches = state.mismatches;
+    if let Some(manifest) = state.manifest {
+        mismatches += report_manifest_mismatches(&manifest, &state.computed);
+    }
+    if mismatches > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} extracted file(s) failed digest verification",
+                mismatches
+            ),
+        ));
+    }
+    Ok(())
+}
+fn extract_indexed_members(
+    tar_file: &str,
+    entries: &[IndexEntry],
+    options: &UnpackOptions,
+) -> Result<(), io::Error> {
+    let mut buffer = vec![0; 512];
+    let mut state = ExtractState::default();
+
+    for entry in entries
+        .iter()
+        .filter(|entry| is_requested(options, &entry.name))
+    {
+        let mut reader = open_member(tar_file, entry)?;
+        loop {
+            if !read_block(&mut reader, &mut buffer)? {
+                return Err(truncated_member(&entry.name));
+            }
+            if extract_member(&mut reader, &buffer, &mut state, options)? {
+                break;
+            }
+        }
+    }
+    // Targets that were not selected are read through the index for the links needing them.
+    let pending = std::mem::take(&mut state.hard_links.pending);
+    for link in &pending {
+        let target = entries
+            .iter()
+            .find(|entry| entry.name == link.target_name && matches!(entry.type_flag, '0' | '\0'));
+        if let (Some(target), false) = (target, link.target.symlink_metadata().is_ok()) {
+            let mut reader = open_member(tar_file, target)?;
+            io::copy(
+                &mut (&mut reader).take(target.data_offset - target.header_offset),
+                &mut io::sink(),
+            )?;
+            state
+                .hard_links
+                .skipped_files
+                .insert(target.name.clone(), &mut reader.take(target.size))?;
+        }
+    }
+    for link in pending {
+        state
+            .hard_links
+            .extract(&link.target_name, &link.target, &link.path)?;
+    }
+    finish_extraction(state)
+}
+fn extract_zip(zip_file: &str, options: &UnpackOptions) -> Result<(), io::Error> {
+    let mut reader = BufReader::new(File::open(zip_file)?);
+    // The central directory gives every offset, so unrequested members are never read.
+    for member in read_central_directory(&mut reader)? {
+        let file_name = member.name.trim_end_matches('/').to_string();
+        if !is_requested(options, &file_name) {
+            continue;
+        }
+        let file_name = options.transforms.apply(&file_name, NameKind::Regular);
+        let path = if member.kind() == EntryKind::Directory {
+            options.directory_path(&file_name)?
+        } else {
+            options.output_path(&file_name)?
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match member.kind() {
+            EntryKind::Directory => fs::create_dir_all(&path)?,
+            EntryKind::Symlink => std::os::unix::fs::symlink(
+                options.transforms.apply(
+                    &String::from_utf8_lossy(&read_member_data(&mut reader, &member)?),
+                    NameKind::SymlinkTarget,
+                ),
+                &path,
+            )?,
+            EntryKind::File => {
+                let mut file = create_file(&path)?;
+                if let Err(e) = copy_member_data(&mut reader, &member, &mut file) {
+                    drop(file);
+                    fs::remove_file(&path)?;
+                    return Err(e);
+                }
             }
             _ => {
-                println!("Unknown type flag: {}", type_flag);
+                println!(
+                    "Skipping {}: unsupported file type in zip archive",
+                    file_name
+                );
+                continue;
             }
         }
+        if member.unix_mode() & 0o7777 != 0 && member.kind() != EntryKind::Symlink {
+            fs::set_permissions(
+                &path,
+                fs::Permissions::from_mode(member.unix_mode() & 0o7777),
+            )?;
+        }
     }
     Ok(())
 }
+pub fn extract_entries(entries: Vec<Entry>, options: &UnpackOptions) -> Result<(), io::Error> {
+    let mut resolver = IdResolver::default();
+    let mut hard_links = HardLinks::default();
+    let link_targets: HashSet<String> = entries
+        .iter()
+        .filter(|entry| entry.kind == EntryKind::HardLink && is_requested(options, &entry.name))
+        .map(|entry| entry.link_name.clone())
+        .collect();
+    for mut entry in entries {
+        if !is_requested(options, &entry.name) {
+            if entry.kind == EntryKind::File && link_targets.contains(&entry.name) {
+                hard_links
+                    .skipped_files
+                    .insert(entry.name, &mut entry.data.as_slice())?;
+            }
+            continue;
+        }
+        let link_target = entry.link_name.clone();
+        options.transforms.apply_to_entry(&mut entry);
+        let path = if entry.kind == EntryKind::Directory {
+            options.directory_path(&entry.name)?
+        } else {
+            options.output_path(&entry.name)?
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mode = Mode::from_bits_truncate(entry.mode);
+        let device = ((entry.device.0 << 8) | entry.device.1) as dev_t;
+        match entry.kind {
+            EntryKind::File => create_file(&path)?.write_all(&entry.data)?,
+            EntryKind::HardLink => {
+                hard_links.extract(&link_target, &options.output_path(&entry.link_name)?, &path)?;
+                if path.symlink_metadata().is_err() {
+                    continue;
+                }
+            }
+            EntryKind::Symlink => std::os::unix::fs::symlink(&entry.link_name, &path)?,
+            EntryKind::Directory => fs::create_dir_all(&path)?,
+            EntryKind::Fifo => mkfifo(&path, mode)?,
+            EntryKind::CharDevice | EntryKind::BlockDevice => {
+                let kind = if entry.kind == EntryKind::CharDevice {
+                    nix::sys::stat::SFlag::S_IFCHR
+                } else {
+                    nix::sys::stat::SFlag::S_IFBLK
+                };
+                if let Err(e) = mknod(&path, kind, mode, device) {
+                    println!("Error:{e}. Creating {} needs root.", entry.name);
+                    continue;
+                }
+            }
+        }
+        // Changing the owner clears setuid and setgid bits, so it goes first.
+        restore_owner(
+            &path,
+            &entry.user_name,
+            &entry.group_name,
+            (entry.uid, entry.gid),
+            &mut resolver,
+            options,
+        )?;
+        if entry.kind != EntryKind::Symlink {
+            fs::set_permissions(&path, fs::Permissions::from_mode(entry.mode))?;
+        }
+    }
+    hard_links.finish()
+}
+pub fn extract_files(tar_file: &str, options: &UnpackOptions) -> Result<(), io::Error> {
+    check_signature(tar_file, options)?;
+    if is_zip(tar_file)? {
+        return extract_zip(tar_file, options);
+    }
+    if !options.members.is_empty() && options.volumes.is_empty() && is_seekable_archive(tar_file)? {
+        if let Some(entries) = read_index(tar_file)? {
+            return extract_indexed_members(tar_file, &entries, options);
+        }
+    }
+
+    let mut reader = BufReader::new(open_archive_volumes(tar_file, &options.volumes)?);
+    if is_cpio(reader.fill_buf()?) {
+        return extract_entries(read_cpio_entries(&mut reader)?, options);
+    }
+    let link_targets = if options.members.is_empty() {
+        None
+    } else {
+        Some(selected_link_targets(tar_file, options)?)
+    };
+    extract_tar(&mut reader, options, link_targets)
+}
+/// Reads the archive once ahead of extracting it to learn which files the selected hard
+/// links point to, so that only those have to be kept while other members are skipped.
+fn selected_link_targets(
+    tar_file: &str,
+    options: &UnpackOptions,
+) -> Result<HashSet<String>, io::Error> {
+    let mut reader = open_archive_volumes(tar_file, &options.volumes)?;
+    let mut buffer = vec![0; 512];
+    let mut pax_path = None;
+    let mut targets = HashSet::new();
+
+    while read_block(&mut reader, &mut buffer)? {
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        let header = UStarHeader::from_bytes(&buffer);
+        let size = header.data_size();
+        let mut content = Vec::new();
+        let data = if header.type_flag() == 'x' {
+            (&mut reader).take(size as u64).read_to_end(&mut content)? as u64
+        } else {
+            io::copy(&mut (&mut reader).take(size as u64), &mut io::sink())?
+        };
+        if data < size as u64 {
+            return Err(truncated_member(&header.file_name()));
+        }
+        io::copy(
+            &mut (&mut reader).take(((512 - (size % 512)) % 512) as u64),
+            &mut io::sink(),
+        )?;
+        match header.type_flag() {
+            'x' => pax_path = parse_pax_records(&content).remove("path"),
+            'g' => {}
+            type_flag => {
+                let file_name = pax_path.take().unwrap_or_else(|| header.file_name());
+                if type_flag == '1' && is_requested(options, &file_name) {
+                    targets.insert(header.link_name());
+                }
+            }
+        }
+    }
+    Ok(targets)
+}
+pub fn extract_stream(reader: &mut dyn Read, options: &UnpackOptions) -> Result<(), io::Error> {
+    extract_tar(reader, options, None)
+}
+fn extract_tar(
+    reader: &mut dyn Read,
+    options: &UnpackOptions,
+    link_targets: Option<HashSet<String>>,
+) -> Result<(), io::Error> {
+    let mut buffer = vec![0; 512];
+    let mut state = ExtractState {
+        link_targets,
+        ..ExtractState::default()
+    };
+
+    loop {
+        if !read_block(reader, &mut buffer)? {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "end-of-archive marker is missing, the archive is truncated",
+            ));
+        }
+        if buffer.iter().all(|&b| b == 0) {
+            break;
+        }
+        extract_member(reader, &buffer, &mut state, options)?;
+    }
+    finish_extraction(state)
+}
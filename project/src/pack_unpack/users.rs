@@ -0,0 +1,98 @@
+use nix::unistd::{Gid, Group, Uid, User};
+use std::collections::HashMap;
+use std::fs;
+
+/// Maps uids and gids to names and back. Lookups go through the C library, so LDAP or SSSD
+/// accounts resolve too, and fall back to reading /etc/passwd and /etc/group; every answer,
+/// including "unknown", is cached for the rest of the run.
+#[derive(Debug, Default)]
+pub struct IdResolver {
+    user_names: HashMap<u32, Option<String>>,
+    group_names: HashMap<u32, Option<String>>,
+    user_ids: HashMap<String, Option<u32>>,
+    group_ids: HashMap<String, Option<u32>>,
+}
+fn database_entries(database: &str) -> Vec<(String, u32)> {
+    fs::read_to_string(database)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse().ok()?;
+            Some((name.to_string(), id))
+        })
+        .collect()
+}
+fn database_name(database: &str, id: u32) -> Option<String> {
+    database_entries(database)
+        .into_iter()
+        .find(|(_, entry_id)| *entry_id == id)
+        .map(|(name, _)| name)
+}
+fn database_id(database: &str, name: &str) -> Option<u32> {
+    database_entries(database)
+        .into_iter()
+        .find(|(entry_name, _)| entry_name == name)
+        .map(|(_, id)| id)
+}
+impl IdResolver {
+    pub fn user_name(&mut self, uid: u32) -> Option<String> {
+        self.user_names
+            .entry(uid)
+            .or_insert_with(|| match User::from_uid(Uid::from_raw(uid)) {
+                Ok(user) => user.map(|user| user.name),
+                Err(_) => database_name("/etc/passwd", uid),
+            })
+            .clone()
+    }
+    pub fn group_name(&mut self, gid: u32) -> Option<String> {
+        self.group_names
+            .entry(gid)
+            .or_insert_with(|| match Group::from_gid(Gid::from_raw(gid)) {
+                Ok(group) => group.map(|group| group.name),
+                Err(_) => database_name("/etc/group", gid),
+            })
+            .clone()
+    }
+    pub fn user_id(&mut self, name: &str) -> Option<u32> {
+        *self
+            .user_ids
+            .entry(name.to_string())
+            .or_insert_with(|| match User::from_name(name) {
+                Ok(user) => user.map(|user| user.uid.as_raw()),
+                Err(_) => database_id("/etc/passwd", name),
+            })
+    }
+    pub fn group_id(&mut self, name: &str) -> Option<u32> {
+        *self
+            .group_ids
+            .entry(name.to_string())
+            .or_insert_with(|| match Group::from_name(name) {
+                Ok(group) => group.map(|group| group.gid.as_raw()),
+                Err(_) => database_id("/etc/group", name),
+            })
+    }
+    /// The ids to give an extracted member: a name known on this system wins over the stored
+    /// id, as with tar, unless `numeric_owner` asks for the stored ids.
+    pub fn owner_ids(
+        &mut self,
+        user_name: &str,
+        group_name: &str,
+        ids: (u32, u32),
+        numeric_owner: bool,
+    ) -> (u32, u32) {
+        if numeric_owner {
+            return ids;
+        }
+        let uid = Some(user_name)
+            .filter(|name| !name.is_empty())
+            .and_then(|name| self.user_id(name))
+            .unwrap_or(ids.0);
+        let gid = Some(group_name)
+            .filter(|name| !name.is_empty())
+            .and_then(|name| self.group_id(name))
+            .unwrap_or(ids.1);
+        (uid, gid)
+    }
+}
@@ -0,0 +1,309 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub fn parse_volume_size(text: &str) -> Result<u64, io::Error> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid volume size '{}'", text),
+        )
+    };
+    let (digits, multiplier) = match text.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&text[..text.len() - 1], 1u64 << 10),
+        Some('M') => (&text[..text.len() - 1], 1 << 20),
+        Some('G') => (&text[..text.len() - 1], 1 << 30),
+        Some('T') => (&text[..text.len() - 1], 1 << 40),
+        _ => (text, 1),
+    };
+    let size = digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(invalid)?;
+    let size = size - size % 512;
+    if size < 2048 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "volume size must be at least 2048 bytes",
+        ));
+    }
+    Ok(size)
+}
+pub fn volume_name(archive_file_name: &str, index: usize) -> String {
+    format!("{}.{:03}", archive_file_name, index)
+}
+pub fn is_first_volume(archive_file: &str) -> bool {
+    archive_file.ends_with(".001")
+}
+fn octal_value(field: &[u8]) -> u64 {
+    let digits: String = field
+        .iter()
+        .map(|&b| b as char)
+        .skip_while(|c| *c == ' ')
+        .take_while(|c| c.is_digit(8))
+        .collect();
+    u64::from_str_radix(&digits, 8).unwrap_or(0)
+}
+fn set_octal(field: &mut [u8], value: u64) -> Result<(), io::Error> {
+    let text = format!("{:0>width$o}\0", value, width = field.len() - 1);
+    if text.len() > field.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} does not fit in a continuation header", value),
+        ));
+    }
+    field.copy_from_slice(text.as_bytes());
+    Ok(())
+}
+fn set_checksum(block: &mut [u8]) {
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+    let checksum_str = format!("{:06o}\0 ", checksum);
+    block[148..156].copy_from_slice(checksum_str.as_bytes());
+}
+struct Member {
+    header_offset: usize,
+    data_offset: usize,
+    data_size: usize,
+}
+fn find_members(tar_buffer: &[u8]) -> Vec<Member> {
+    let mut members = Vec::new();
+    let mut offset = 0;
+    while offset + 512 <= tar_buffer.len() {
+        let header = &tar_buffer[offset..offset + 512];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let data_size = match header[156] {
+            b'1' | b'2' | b'3' | b'4' | b'5' | b'6' => 0,
+            _ => octal_value(&header[124..136]) as usize,
+        };
+        let padded = data_size.div_ceil(512) * 512;
+        members.push(Member {
+            header_offset: offset,
+            data_offset: offset + 512,
+            data_size,
+        });
+        offset += 512 + padded;
+    }
+    members
+}
+fn continuation_header(
+    tar_buffer: &[u8],
+    member: &Member,
+    written: usize,
+) -> Result<Vec<u8>, io::Error> {
+    let mut block = tar_buffer[member.header_offset..member.header_offset + 512].to_vec();
+    block[156] = b'M';
+    set_octal(&mut block[124..136], (member.data_size - written) as u64)?;
+    block[257..265].copy_from_slice(b"ustar  \0");
+    block[345..500].fill(0);
+    set_octal(&mut block[369..381], written as u64)?;
+    set_checksum(&mut block);
+    Ok(block)
+}
+pub fn write_volumes(
+    tar_buffer: &[u8],
+    archive_file_name: &str,
+    volume_size: u64,
+) -> Result<Vec<String>, io::Error> {
+    let volume_size = volume_size as usize;
+    let members = find_members(tar_buffer);
+    let mut volume_names = Vec::new();
+    let mut position = 0;
+
+    while position < tar_buffer.len() {
+        let name = volume_name(archive_file_name, volume_names.len() + 1);
+        let mut file = File::create(&name)?;
+        let mut header_len = 0;
+
+        if let Some(member) = members.iter().find(|member| {
+            position >= member.data_offset && position < member.data_offset + member.data_size
+        }) {
+            let written = position - member.data_offset;
+            file.write_all(&continuation_header(tar_buffer, member, written)?)?;
+            header_len = 512;
+        }
+        let end = position
+            .saturating_add(volume_size - header_len)
+            .min(tar_buffer.len());
+        file.write_all(&tar_buffer[position..end])?;
+        position = end;
+
+        volume_names.push(name);
+    }
+    Ok(volume_names)
+}
+/// Follows the tar blocks handed out so far, so that a volume break inside a member's data can
+/// be matched against the `M` header that has to open the next volume.
+#[derive(Default)]
+struct StreamPosition {
+    header: Vec<u8>,
+    name: Vec<u8>,
+    size: u64,
+    emitted: u64,
+    padding: u64,
+}
+impl StreamPosition {
+    fn advance(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let consumed = if self.emitted < self.size {
+                let consumed = (self.size - self.emitted).min(data.len() as u64);
+                self.emitted += consumed;
+                if self.emitted == self.size {
+                    self.padding = (512 - self.size % 512) % 512;
+                }
+                consumed as usize
+            } else if self.padding > 0 {
+                let consumed = self.padding.min(data.len() as u64);
+                self.padding -= consumed;
+                consumed as usize
+            } else {
+                let consumed = (512 - self.header.len()).min(data.len());
+                self.header.extend_from_slice(&data[..consumed]);
+                if self.header.len() == 512 {
+                    self.start_member();
+                }
+                consumed
+            };
+            data = &data[consumed..];
+        }
+    }
+    fn start_member(&mut self) {
+        let header = std::mem::take(&mut self.header);
+        self.name = header[..100].to_vec();
+        self.size = match header[156] {
+            b'1' | b'2' | b'3' | b'4' | b'5' | b'6' => 0,
+            _ => octal_value(&header[124..136]),
+        };
+        self.emitted = 0;
+    }
+    fn in_member_data(&self) -> bool {
+        self.emitted < self.size
+    }
+    fn continued_by(&self, block: &[u8]) -> bool {
+        block[156] == b'M'
+            && block[..100] == self.name[..]
+            && octal_value(&block[369..381]) == self.emitted
+    }
+    fn member_name(&self) -> String {
+        let end = self
+            .name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.name.len());
+        String::from_utf8_lossy(&self.name[..end]).to_string()
+    }
+}
+pub struct MultiVolumeReader {
+    first_volume: String,
+    listed_volumes: Vec<String>,
+    index: usize,
+    current_volume: String,
+    current: BufReader<File>,
+    trailing_zeros: usize,
+    position: StreamPosition,
+}
+impl MultiVolumeReader {
+    pub fn open(first_volume: &str, listed_volumes: &[String]) -> Result<Self, io::Error> {
+        Ok(MultiVolumeReader {
+            first_volume: first_volume.to_string(),
+            listed_volumes: listed_volumes.to_vec(),
+            index: 1,
+            current_volume: first_volume.to_string(),
+            current: BufReader::new(File::open(first_volume)?),
+            trailing_zeros: 0,
+            position: StreamPosition::default(),
+        })
+    }
+    fn next_volume_name(&self) -> Option<String> {
+        if !self.listed_volumes.is_empty() {
+            return self.listed_volumes.get(self.index - 1).cloned();
+        }
+        let base = self.first_volume.strip_suffix(".001")?;
+        Some(volume_name(base, self.index + 1))
+    }
+    fn open_next_volume(&mut self) -> Result<bool, io::Error> {
+        let next_volume = match self.next_volume_name() {
+            Some(next_volume) if Path::new(&next_volume).exists() => next_volume,
+            next_volume => {
+                if self.trailing_zeros >= 1024 {
+                    return Ok(false);
+                }
+                let message = match next_volume {
+                    Some(next_volume) => format!(
+                        "volume {} is missing (needed after {})",
+                        next_volume, self.current_volume
+                    ),
+                    None => format!(
+                        "archive continues after {} but no further volume was given",
+                        self.current_volume
+                    ),
+                };
+                return Err(io::Error::new(io::ErrorKind::NotFound, message));
+            }
+        };
+
+        let mut reader = BufReader::new(File::open(&next_volume)?);
+        let mut block = vec![0; 512];
+        let has_header = reader.read_exact(&mut block).is_ok() && block[156] == b'M';
+        if self.position.in_member_data() {
+            if !has_header || !self.position.continued_by(&block) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "volume {} does not continue {} at offset {}",
+                        next_volume,
+                        self.position.member_name(),
+                        self.position.emitted
+                    ),
+                ));
+            }
+            println!("Continuing in volume {}", next_volume);
+        } else if has_header {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "volume {} continues a member but {} ended between members",
+                    next_volume, self.current_volume
+                ),
+            ));
+        } else {
+            reader.seek(SeekFrom::Start(0))?;
+        }
+        self.current = reader;
+        self.current_volume = next_volume;
+        self.index += 1;
+        Ok(true)
+    }
+}
+impl Read for MultiVolumeReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.current.read(buffer)?;
+            if read > 0 || buffer.is_empty() {
+                self.position.advance(&buffer[..read]);
+                match buffer[..read].iter().rposition(|&b| b != 0) {
+                    Some(last_data) => self.trailing_zeros = read - last_data - 1,
+                    None => self.trailing_zeros += read,
+                }
+                return Ok(read);
+            }
+            if !self.open_next_volume()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+pub fn remove_stale_volumes(archive_file_name: &str, from_index: usize) -> Result<(), io::Error> {
+    let mut index = from_index;
+    loop {
+        let name = volume_name(archive_file_name, index);
+        if !Path::new(&name).exists() {
+            return Ok(());
+        }
+        fs::remove_file(name)?;
+        index += 1;
+    }
+}
@@ -0,0 +1,502 @@
+use crate::pack_unpack::entry::{Entry, EntryKind};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc, CrcReader};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const ZIP64_EXTRA_TAG: u16 = 0x0001;
+const ZIP64_LIMIT: u64 = 0xffff_ffff;
+const EXTENDED_TIMESTAMP_TAG: u16 = 0x5455;
+const UTF8_NAME_FLAG: u16 = 1 << 11;
+const VERSION_MADE_BY_UNIX: u16 = (3 << 8) | 20;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+#[derive(Debug, Clone)]
+pub struct ZipMember {
+    pub name: String,
+    pub method: u16,
+    pub crc: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    pub local_header_offset: u64,
+    pub version_made_by: u16,
+    pub external_attributes: u32,
+    pub mtime: i64,
+}
+impl ZipMember {
+    pub fn kind(&self) -> EntryKind {
+        let unix_mode = self.unix_mode();
+        match EntryKind::from_file_type_bits(unix_mode) {
+            Some(kind) if unix_mode != 0 => kind,
+            _ if self.name.ends_with('/') => EntryKind::Directory,
+            _ => EntryKind::File,
+        }
+    }
+    pub fn unix_mode(&self) -> u32 {
+        if self.version_made_by >> 8 == 3 {
+            self.external_attributes >> 16
+        } else {
+            0
+        }
+    }
+}
+pub fn is_zip(archive_file: &str) -> Result<bool, io::Error> {
+    let mut magic = [0u8; 4];
+    let read = File::open(archive_file)?.read(&mut magic)?;
+    Ok(read == 4 && (&magic == b"PK\x03\x04" || &magic == b"PK\x05\x06"))
+}
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+fn zip64_extra(values: &[u8]) -> Vec<u8> {
+    let mut extra = ZIP64_EXTRA_TAG.to_le_bytes().to_vec();
+    extra.extend_from_slice(&(values.len() as u16).to_le_bytes());
+    extra.extend_from_slice(values);
+    extra
+}
+fn invalid_zip(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+// DOS timestamps have no time zone; like Info-ZIP on a UTC system we treat them as UTC.
+fn dos_date_time(mtime: i64) -> (u16, u16) {
+    let days = mtime.div_euclid(86400);
+    let seconds = mtime.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((seconds / 3600) << 11) | (((seconds % 3600) / 60) << 5) | ((seconds % 60) / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+fn unix_time(time: u16, date: u16) -> i64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0x0f).max(1) as i64;
+    let day = (date & 0x1f).max(1) as i64;
+    days_from_civil(year, month, day) * 86400
+        + (time >> 11) as i64 * 3600
+        + ((time >> 5) & 0x3f) as i64 * 60
+        + (time & 0x1f) as i64 * 2
+}
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+fn extended_timestamp(extra: &[u8]) -> Option<i64> {
+    let mut offset = 0;
+    while offset + 4 <= extra.len() {
+        let tag = u16_at(extra, offset);
+        let size = u16_at(extra, offset + 2) as usize;
+        let field = extra.get(offset + 4..offset + 4 + size)?;
+        if tag == EXTENDED_TIMESTAMP_TAG && size >= 5 && field[0] & 1 != 0 {
+            return Some(u32_at(field, 1) as i32 as i64);
+        }
+        offset += 4 + size;
+    }
+    None
+}
+fn find_end_of_central_directory<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<(u64, u64, u64), io::Error> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = file_len.min(22 + 65535);
+    let tail_start = file_len - tail_len;
+    reader.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = vec![0; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(&tail, i) == END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+        .ok_or_else(|| invalid_zip("end of central directory not found, not a zip archive"))?;
+    let count = u16_at(&tail, end + 10) as u64;
+    let directory_size = u32_at(&tail, end + 12) as u64;
+    let directory_offset = u32_at(&tail, end + 16) as u64;
+    if count != 0xffff && directory_size != ZIP64_LIMIT && directory_offset != ZIP64_LIMIT {
+        return Ok((count, directory_size, directory_offset));
+    }
+
+    let locator = tail_start + end as u64;
+    if locator < 20 {
+        return Err(invalid_zip(
+            "zip64 end of central directory locator is missing",
+        ));
+    }
+    reader.seek(SeekFrom::Start(locator - 20))?;
+    let mut locator = [0u8; 20];
+    reader.read_exact(&mut locator)?;
+    if u32_at(&locator, 0) != ZIP64_LOCATOR_SIGNATURE {
+        return Err(invalid_zip(
+            "zip64 end of central directory locator is missing",
+        ));
+    }
+    reader.seek(SeekFrom::Start(u64_at(&locator, 8)))?;
+    let mut record = [0u8; 56];
+    reader.read_exact(&mut record)?;
+    if u32_at(&record, 0) != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+        return Err(invalid_zip(
+            "zip64 end of central directory record is damaged",
+        ));
+    }
+    Ok((
+        u64_at(&record, 32),
+        u64_at(&record, 40),
+        u64_at(&record, 48),
+    ))
+}
+fn apply_zip64_extra(extra: &[u8], member: &mut ZipMember) {
+    let mut offset = 0;
+    while offset + 4 <= extra.len() {
+        let tag = u16_at(extra, offset);
+        let size = u16_at(extra, offset + 2) as usize;
+        let Some(field) = extra.get(offset + 4..offset + 4 + size) else {
+            return;
+        };
+        if tag == ZIP64_EXTRA_TAG {
+            let mut values = field.chunks_exact(8).map(|value| u64_at(value, 0));
+            for slot in [
+                &mut member.size,
+                &mut member.compressed_size,
+                &mut member.local_header_offset,
+            ] {
+                if *slot == ZIP64_LIMIT {
+                    match values.next() {
+                        Some(value) => *slot = value,
+                        None => return,
+                    }
+                }
+            }
+            return;
+        }
+        offset += 4 + size;
+    }
+}
+pub fn read_central_directory<R: Read + Seek>(reader: &mut R) -> Result<Vec<ZipMember>, io::Error> {
+    let (count, directory_size, directory_offset) = find_end_of_central_directory(reader)?;
+    reader.seek(SeekFrom::Start(directory_offset))?;
+    let mut directory = Vec::new();
+    reader.take(directory_size).read_to_end(&mut directory)?;
+
+    let mut members = Vec::new();
+    let mut offset = 0;
+    for _ in 0..count {
+        if offset + 46 > directory.len() || u32_at(&directory, offset) != CENTRAL_HEADER_SIGNATURE {
+            return Err(invalid_zip("central directory is damaged"));
+        }
+        let name_len = u16_at(&directory, offset + 28) as usize;
+        let extra_len = u16_at(&directory, offset + 30) as usize;
+        let comment_len = u16_at(&directory, offset + 32) as usize;
+        let name_start = offset + 46;
+        let extra_start = name_start + name_len;
+        if extra_start + extra_len + comment_len > directory.len() {
+            return Err(invalid_zip("central directory is damaged"));
+        }
+        let extra = &directory[extra_start..extra_start + extra_len];
+
+        let mut member = ZipMember {
+            name: String::from_utf8_lossy(&directory[name_start..extra_start]).to_string(),
+            method: u16_at(&directory, offset + 10),
+            crc: u32_at(&directory, offset + 16),
+            compressed_size: u32_at(&directory, offset + 20) as u64,
+            size: u32_at(&directory, offset + 24) as u64,
+            local_header_offset: u32_at(&directory, offset + 42) as u64,
+            version_made_by: u16_at(&directory, offset + 4),
+            external_attributes: u32_at(&directory, offset + 38),
+            mtime: extended_timestamp(extra).unwrap_or_else(|| {
+                unix_time(
+                    u16_at(&directory, offset + 12),
+                    u16_at(&directory, offset + 14),
+                )
+            }),
+        };
+        apply_zip64_extra(extra, &mut member);
+        members.push(member);
+        offset = extra_start + extra_len + comment_len;
+    }
+    Ok(members)
+}
+/// Streams a member's uncompressed data into `output`, checking its size and CRC once the last
+/// byte is through. On an error `output` may already hold part of the data.
+pub fn copy_member_data<R: Read + Seek>(
+    reader: &mut R,
+    member: &ZipMember,
+    output: &mut dyn Write,
+) -> Result<u64, io::Error> {
+    reader.seek(SeekFrom::Start(member.local_header_offset))?;
+    let mut local_header = [0u8; 30];
+    reader.read_exact(&mut local_header)?;
+    if u32_at(&local_header, 0) != LOCAL_HEADER_SIGNATURE {
+        return Err(invalid_zip(&format!(
+            "local header of '{}' is damaged",
+            member.name
+        )));
+    }
+    let skip = u16_at(&local_header, 26) as i64 + u16_at(&local_header, 28) as i64;
+    reader.seek(SeekFrom::Current(skip))?;
+
+    let compressed = reader.take(member.compressed_size);
+    let data: Box<dyn Read + '_> = match member.method {
+        METHOD_STORED => Box::new(compressed),
+        METHOD_DEFLATED => Box::new(DeflateDecoder::new(compressed)),
+        method => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "'{}' uses compression method {}, only stored and deflate are supported",
+                    member.name, method
+                ),
+            ))
+        }
+    };
+    // One byte past the declared size is enough to tell that a member lies about its size.
+    let mut data = CrcReader::new(data.take(member.size + 1));
+    let copied = io::copy(&mut data, output)?;
+    if copied != member.size || data.crc().sum() != member.crc {
+        return Err(invalid_zip(&format!(
+            "'{}' failed its CRC check",
+            member.name
+        )));
+    }
+    Ok(copied)
+}
+pub fn read_member_data<R: Read + Seek>(
+    reader: &mut R,
+    member: &ZipMember,
+) -> Result<Vec<u8>, io::Error> {
+    let mut data = Vec::new();
+    copy_member_data(reader, member, &mut data)?;
+    Ok(data)
+}
+pub fn read_zip_entries<R: Read + Seek>(reader: &mut R) -> Result<Vec<Entry>, io::Error> {
+    let mut entries = Vec::new();
+    for member in read_central_directory(reader)? {
+        let data = read_member_data(reader, &member)?;
+        let kind = member.kind();
+        let mut entry = Entry::new(&member.name, kind);
+        if member.unix_mode() != 0 {
+            entry.mode = member.unix_mode() & 0o7777;
+        }
+        entry.mtime = member.mtime;
+        if kind == EntryKind::Symlink {
+            entry.link_name = String::from_utf8_lossy(&data).to_string();
+        } else {
+            entry.data = data;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+pub fn write_zip_entries(entries: &[Entry], writer: &mut dyn Write) -> Result<usize, io::Error> {
+    let mut file_data: HashMap<&str, &[u8]> = HashMap::new();
+    let mut central_directory = Vec::new();
+    let mut offset: u64 = 0;
+    let mut count = 0;
+
+    if entries
+        .iter()
+        .any(|entry| entry.uid != 0 || entry.gid != 0 || !entry.user_name.is_empty())
+    {
+        println!("Warning: zip archives do not store owners, ownership is lost");
+    }
+
+    for entry in entries {
+        let data: &[u8] = match entry.kind {
+            EntryKind::File => {
+                file_data.insert(&entry.name, &entry.data);
+                &entry.data
+            }
+            EntryKind::HardLink => match file_data.get(entry.link_name.as_str()) {
+                Some(data) => {
+                    println!(
+                        "Warning: {}: zip has no hard links, stored as a copy of {}",
+                        entry.name, entry.link_name
+                    );
+                    data
+                }
+                None => {
+                    println!(
+                        "Warning: {}: hard link target {} is not in the archive, skipped",
+                        entry.name, entry.link_name
+                    );
+                    continue;
+                }
+            },
+            EntryKind::Symlink => entry.link_name.as_bytes(),
+            EntryKind::Directory => &[],
+            EntryKind::CharDevice | EntryKind::BlockDevice | EntryKind::Fifo => {
+                println!(
+                    "Warning: {}: zip cannot store device nodes or FIFOs, skipped",
+                    entry.name
+                );
+                continue;
+            }
+        };
+        let name = if entry.kind == EntryKind::Directory {
+            format!("{}/", entry.name)
+        } else {
+            entry.name.clone()
+        };
+        let kind = if entry.kind == EntryKind::HardLink {
+            EntryKind::File
+        } else {
+            entry.kind
+        };
+
+        let mut crc = Crc::new();
+        crc.update(data);
+        let mut method = METHOD_STORED;
+        let mut stored = data.to_vec();
+        if kind == EntryKind::File && !data.is_empty() {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            let deflated = encoder.finish()?;
+            if deflated.len() < data.len() {
+                method = METHOD_DEFLATED;
+                stored = deflated;
+            }
+        }
+        let (time, date) = dos_date_time(entry.mtime);
+        let mut timestamp = Vec::with_capacity(9);
+        timestamp.extend_from_slice(&EXTENDED_TIMESTAMP_TAG.to_le_bytes());
+        timestamp.extend_from_slice(&5u16.to_le_bytes());
+        timestamp.push(1);
+        timestamp.extend_from_slice(&(entry.mtime as u32).to_le_bytes());
+
+        // Sizes and offsets that do not fit in 32 bits move to a zip64 extra field.
+        let large_sizes = data.len() as u64 >= ZIP64_LIMIT || stored.len() as u64 >= ZIP64_LIMIT;
+        let large_offset = offset >= ZIP64_LIMIT;
+        let version_needed: u16 = if large_sizes || large_offset { 45 } else { 20 };
+        let flags: u16 = if name.is_ascii() { 0 } else { UTF8_NAME_FLAG };
+
+        let mut common = Vec::with_capacity(14);
+        common.extend_from_slice(&version_needed.to_le_bytes());
+        common.extend_from_slice(&flags.to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        let mut sizes = Vec::with_capacity(8);
+        let mut zip64_sizes = Vec::new();
+        if large_sizes {
+            sizes.extend_from_slice(&(ZIP64_LIMIT as u32).to_le_bytes());
+            sizes.extend_from_slice(&(ZIP64_LIMIT as u32).to_le_bytes());
+            zip64_sizes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            zip64_sizes.extend_from_slice(&(stored.len() as u64).to_le_bytes());
+        } else {
+            sizes.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            sizes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        }
+
+        let mut local_extra = timestamp.clone();
+        if large_sizes {
+            local_extra.extend_from_slice(&zip64_extra(&zip64_sizes));
+        }
+        let mut local_header = LOCAL_HEADER_SIGNATURE.to_le_bytes().to_vec();
+        local_header.extend_from_slice(&common);
+        local_header.extend_from_slice(&sizes);
+        local_header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local_header.extend_from_slice(&(local_extra.len() as u16).to_le_bytes());
+        local_header.extend_from_slice(name.as_bytes());
+        local_header.extend_from_slice(&local_extra);
+        writer.write_all(&local_header)?;
+        writer.write_all(&stored)?;
+
+        let mut central_extra = timestamp;
+        let mut zip64_values = zip64_sizes;
+        if large_offset {
+            zip64_values.extend_from_slice(&offset.to_le_bytes());
+        }
+        if !zip64_values.is_empty() {
+            central_extra.extend_from_slice(&zip64_extra(&zip64_values));
+        }
+        let external_attributes = ((kind.file_type_bits() | entry.mode) << 16)
+            | if kind == EntryKind::Directory {
+                0x10
+            } else {
+                0
+            };
+        central_directory.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        central_directory.extend_from_slice(&VERSION_MADE_BY_UNIX.to_le_bytes());
+        central_directory.extend_from_slice(&common);
+        central_directory.extend_from_slice(&sizes);
+        central_directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central_directory.extend_from_slice(&(central_extra.len() as u16).to_le_bytes());
+        central_directory.extend_from_slice(&0u16.to_le_bytes());
+        central_directory.extend_from_slice(&0u16.to_le_bytes());
+        central_directory.extend_from_slice(&0u16.to_le_bytes());
+        central_directory.extend_from_slice(&external_attributes.to_le_bytes());
+        central_directory.extend_from_slice(&(offset.min(ZIP64_LIMIT) as u32).to_le_bytes());
+        central_directory.extend_from_slice(name.as_bytes());
+        central_directory.extend_from_slice(&central_extra);
+
+        offset += (local_header.len() + stored.len()) as u64;
+        count += 1;
+    }
+
+    writer.write_all(&central_directory)?;
+    let directory_size = central_directory.len() as u64;
+    if count >= 0xffff || offset >= ZIP64_LIMIT || directory_size >= ZIP64_LIMIT {
+        let mut record = ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE
+            .to_le_bytes()
+            .to_vec();
+        record.extend_from_slice(&44u64.to_le_bytes());
+        record.extend_from_slice(&VERSION_MADE_BY_UNIX.to_le_bytes());
+        record.extend_from_slice(&45u16.to_le_bytes());
+        record.extend_from_slice(&[0; 8]);
+        record.extend_from_slice(&(count as u64).to_le_bytes());
+        record.extend_from_slice(&(count as u64).to_le_bytes());
+        record.extend_from_slice(&directory_size.to_le_bytes());
+        record.extend_from_slice(&offset.to_le_bytes());
+        record.extend_from_slice(&ZIP64_LOCATOR_SIGNATURE.to_le_bytes());
+        record.extend_from_slice(&0u32.to_le_bytes());
+        record.extend_from_slice(&(offset + directory_size).to_le_bytes());
+        record.extend_from_slice(&1u32.to_le_bytes());
+        writer.write_all(&record)?;
+    }
+    let mut end = END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes().to_vec();
+    end.extend_from_slice(&[0; 4]);
+    end.extend_from_slice(&(count.min(0xffff) as u16).to_le_bytes());
+    end.extend_from_slice(&(count.min(0xffff) as u16).to_le_bytes());
+    end.extend_from_slice(&(directory_size.min(ZIP64_LIMIT) as u32).to_le_bytes());
+    end.extend_from_slice(&(offset.min(ZIP64_LIMIT) as u32).to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    writer.write_all(&end)?;
+    writer.flush()?;
+    Ok(count)
+}